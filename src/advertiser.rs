@@ -1,7 +1,13 @@
+use crate::aws;
+use crate::config::TestConfig;
+use crate::error::TigerClawError;
+use crate::guardrails::Guardrails;
 use crate::orchestration_status::{MigrationStatus, StepDescriptor};
-use aws_sdk_dynamodb::{
-    Client, Error, types::AttributeValue, types::error::ResourceNotFoundException,
-};
+use crate::retry::{Idempotency, RetryPolicy, should_retry_dynamo};
+use crate::setup::Environment;
+use aws_sdk_dynamodb::{Error, types::AttributeValue, types::error::ResourceNotFoundException};
+use log::{error, info};
+use std::fmt;
 const ADVERTISER_TABLE_NAME: &str = "external-adv-awin-migration";
 
 #[derive(Debug, Clone)]
@@ -35,7 +41,6 @@ impl Advertiser {
             validation_errors.push("External ID is required".to_string());
         }
 
-
         // if we have no validation errors, return Ok() else return error()
         if validation_errors.is_empty() {
             return Ok(());
@@ -43,35 +48,215 @@ impl Advertiser {
 
         Err(validation_errors.join(", "))
     }
+
+    /// Compare this advertiser record against a later snapshot and return every
+    /// field we care about when debugging a step that has changed
+    pub fn diff(&self, after: &Advertiser) -> Vec<FieldChange> {
+        let before_fields = self.diff_fields();
+        let after_fields = after.diff_fields();
+        before_fields
+            .into_iter()
+            .zip(after_fields)
+            .filter(|((_, before), (_, after))| before != after)
+            .map(|((field, before), (_, after))| FieldChange {
+                field,
+                before,
+                after,
+            })
+            .collect()
+    }
+
+    /// Flattens the fields compared by `diff` into printable name/value pairs
+    fn diff_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("migration_status", self.migration_status_string.clone()),
+            ("start_date", format_date(&self.start_date)),
+            ("end_date", format_date(&self.end_date)),
+            ("terms_awin_user_id", self.terms_awin_user_id.clone()),
+            ("terms_status", self.terms_status.clone()),
+            ("terms_timestamp", format_date(&self.terms_timestamp)),
+            ("migration_completed", self.migration_completed.to_string()),
+        ]
+    }
 }
 
-/// Get advertiser by external ID, note: external_id in this case is just the SAS Merchant ID
-pub async fn get_advertiser_by_external_id(external_id: i32) -> Result<Advertiser, Error> {
-    let config = aws_config::from_env()
-        .profile_name("org-adm-springfield-dev-poweruser")
-        .load()
-        .await;
-    let client = Client::new(&config);
-
-    let output = client
-        .scan()
-        .table_name(ADVERTISER_TABLE_NAME)
-        .filter_expression("external_id = :external_id")
-        .expression_attribute_values(":external_id", AttributeValue::S(external_id.to_string()))
-        .send()
+/// A single field that differs between two snapshots of the same advertiser
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub before: String,
+    pub after: String,
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.before, self.after)
+    }
+}
+
+fn format_date(date: &Option<chrono::DateTime<chrono::Utc>>) -> String {
+    match date {
+        Some(date) => date.to_rfc3339(),
+        None => "<none>".to_string(),
+    }
+}
+
+/// Prints a field level diff of an advertiser record captured before and after
+/// an orchestration step. Either snapshot may be missing if the record did not
+/// exist yet or could not be read from DynamoDB
+pub fn print_diff(label: &str, before: Option<&Advertiser>, after: Option<&Advertiser>) {
+    match (before, after) {
+        (Some(before), Some(after)) => {
+            let changes = before.diff(after);
+            if changes.is_empty() {
+                info!("[{}] advertiser record unchanged", label);
+                return;
+            }
+            info!("[{}] advertiser record changed:", label);
+            for change in changes {
+                info!("  {}", change);
+            }
+        }
+        (None, Some(after)) => {
+            info!("[{}] advertiser record created:", label);
+            for (field, value) in after.diff_fields() {
+                info!("  {}: <none> -> {}", field, value);
+            }
+        }
+        (Some(_), None) => {
+            info!(
+                "[{}] advertiser record no longer readable after step",
+                label
+            );
+        }
+        (None, None) => {
+            info!(
+                "[{}] advertiser record not found before or after step",
+                label
+            );
+        }
+    }
+}
+
+/// Checks DynamoDB is reachable with the run's AWS credentials and the
+/// advertiser table exists
+pub async fn check_table(
+    retry: &RetryPolicy,
+    environment: Environment,
+) -> Result<(), TigerClawError> {
+    info!(
+        "Checking the {} table with AWS profile {}",
+        ADVERTISER_TABLE_NAME,
+        aws::profile(environment)?
+    );
+    let client = aws::dynamo_db_client(environment).await?;
+
+    retry
+        .run(
+            "Advertiser table check",
+            Idempotency::Idempotent,
+            should_retry_dynamo,
+            || {
+                client
+                    .describe_table()
+                    .table_name(ADVERTISER_TABLE_NAME)
+                    .send()
+            },
+        )
         .await?;
+    Ok(())
+}
 
-    let items = output.items.unwrap_or_default();
-    //TODO: Handle this better methinks
-    if items.is_empty() {
-        return Err(Error::ResourceNotFoundException(
-            ResourceNotFoundException::builder()
-                .message(format!("No advertiser found for id: {}", external_id))
-                .build(),
+/// Reads the advertiser record of `globals.external_id` from DynamoDB and
+/// checks it is a valid SAS migration record
+pub async fn run(
+    test_config: &TestConfig,
+    retry: &RetryPolicy,
+    environment: Environment,
+    dry_run: bool,
+) -> Result<(), TigerClawError> {
+    let Some(external_id) = test_config.globals.external_id else {
+        return Err(TigerClawError::Config(
+            "external_id is required for dynamo_db".to_string(),
         ));
+    };
+    if dry_run {
+        info!("Dry run, sending {}", describe_lookup(external_id));
     }
+    let advertiser = get_advertiser_by_external_id(external_id, retry, environment).await?;
+    advertiser.validate().map_err(|errors| {
+        let message = format!(
+            "Advertiser record of external_id {} is invalid: {}",
+            external_id, errors
+        );
+        error!("{}", message);
+        TigerClawError::Assertion(message)
+    })?;
+    info!(
+        "Advertiser record of external_id {} is valid, migration status {}",
+        external_id, advertiser.migration_status_string
+    );
+    Ok(())
+}
+
+/// The DynamoDB operation `get_advertiser_by_external_id` sends, for dry runs
+pub fn describe_lookup(external_id: i32) -> String {
+    format!(
+        "DynamoDB Scan {} external_id = {}",
+        ADVERTISER_TABLE_NAME, external_id
+    )
+}
+
+/// Get advertiser by external ID, note: external_id in this case is just the SAS Merchant ID
+pub async fn get_advertiser_by_external_id(
+    external_id: i32,
+    retry: &RetryPolicy,
+    environment: Environment,
+) -> Result<Advertiser, TigerClawError> {
+    find_advertiser_by_external_id(external_id, retry, environment)
+        .await?
+        .ok_or_else(|| {
+            Error::ResourceNotFoundException(
+                ResourceNotFoundException::builder()
+                    .message(format!("No advertiser found for id: {}", external_id))
+                    .build(),
+            )
+            .into()
+        })
+}
 
-    let item = &items[0];
+/// Like `get_advertiser_by_external_id`, but an advertiser that isn't in the
+/// table is `None` rather than an error. DynamoDB errors are still errors
+pub async fn find_advertiser_by_external_id(
+    external_id: i32,
+    retry: &RetryPolicy,
+    environment: Environment,
+) -> Result<Option<Advertiser>, TigerClawError> {
+    let client = aws::dynamo_db_client(environment).await?;
+
+    let output = retry
+        .run(
+            "Advertiser lookup",
+            Idempotency::Idempotent,
+            should_retry_dynamo,
+            || {
+                client
+                    .scan()
+                    .table_name(ADVERTISER_TABLE_NAME)
+                    .filter_expression("external_id = :external_id")
+                    .expression_attribute_values(
+                        ":external_id",
+                        AttributeValue::S(external_id.to_string()),
+                    )
+                    .send()
+            },
+        )
+        .await?;
+
+    let items = output.items.unwrap_or_default();
+    let Some(item) = items.first() else {
+        return Ok(None);
+    };
     let advertiser = Advertiser {
         migration_name: item
             .get("migration_name")
@@ -133,17 +318,26 @@ pub async fn get_advertiser_by_external_id(external_id: i32) -> Result<Advertise
             .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
             .map(|dt| dt.with_timezone(&chrono::Utc)),
     };
-    Ok(advertiser)
+    Ok(Some(advertiser))
 }
 
 // NOTE: This function is not currently used as I want to make sure
 // I don't accidentally delete important data
-pub async fn delete_advertiser_by_external_id(external_id: i32) -> Result<(), Error> {
-    let config = aws_config::from_env()
-        .profile_name("org-adm-springfield-dev-poweruser")
-        .load()
-        .await;
-    let client = Client::new(&config);
+pub async fn delete_advertiser_by_external_id(
+    external_id: i32,
+    guardrails: &Guardrails,
+) -> Result<(), TigerClawError> {
+    if !guardrails.allow_external_id(
+        "delete advertisers",
+        external_id,
+        &format!(
+            "DynamoDB DeleteItem {} external_id = {}",
+            ADVERTISER_TABLE_NAME, external_id
+        ),
+    )? {
+        return Ok(());
+    }
+    let client = aws::dynamo_db_client(guardrails.environment()).await?;
 
     client
         .delete_item()
@@ -157,12 +351,21 @@ pub async fn delete_advertiser_by_external_id(external_id: i32) -> Result<(), Er
 
 // NOTE: This function is not currently used as I want to make sure
 // I don't accidentally delete important data
-pub async fn delete_advertiser_by_awin_advertiser_id(awin_id: i32) -> Result<(), Error> {
-    let config = aws_config::from_env()
-        .profile_name("org-adm-springfield-dev-poweruser")
-        .load()
-        .await;
-    let client = Client::new(&config);
+pub async fn delete_advertiser_by_awin_advertiser_id(
+    awin_id: i32,
+    guardrails: &Guardrails,
+) -> Result<(), TigerClawError> {
+    if !guardrails.allow_advertiser_id(
+        "delete advertisers",
+        awin_id,
+        &format!(
+            "DynamoDB DeleteItem {} awin_id = {}",
+            ADVERTISER_TABLE_NAME, awin_id
+        ),
+    )? {
+        return Ok(());
+    }
+    let client = aws::dynamo_db_client(guardrails.environment()).await?;
 
     client
         .delete_item()
@@ -178,10 +381,59 @@ pub async fn delete_advertiser_by_awin_advertiser_id(awin_id: i32) -> Result<(),
 pub mod tests {
     use super::*;
 
+    fn test_advertiser() -> Advertiser {
+        Advertiser {
+            migration_name: "sas".to_string(),
+            awin_id: "424242".to_string(),
+            external_id: "44911".to_string(),
+            end_date: None,
+            migration_completed: false,
+            migration_status_string: "INIT_DONE".to_string(),
+            migration_status: MigrationStatus::InitDone(StepDescriptor::Init),
+            start_date: None,
+            terms_awin_user_id: "1".to_string(),
+            terms_status: "Pending".to_string(),
+            terms_timestamp: None,
+        }
+    }
+
+    #[test]
+    fn diff_reports_changed_fields_only() {
+        let before = test_advertiser();
+        let mut after = test_advertiser();
+        after.migration_status_string = "VALID_DONE".to_string();
+        after.migration_completed = true;
+
+        let changes = before.diff(&after);
+        assert_eq!(
+            changes,
+            vec![
+                FieldChange {
+                    field: "migration_status",
+                    before: "INIT_DONE".to_string(),
+                    after: "VALID_DONE".to_string(),
+                },
+                FieldChange {
+                    field: "migration_completed",
+                    before: "false".to_string(),
+                    after: "true".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_of_identical_records_is_empty() {
+        let advertiser = test_advertiser();
+        assert!(advertiser.diff(&advertiser.clone()).is_empty());
+    }
+
     #[tokio::test]
     async fn test_get_advertiser() {
         let external_id = 424242;
-        let advertiser = get_advertiser_by_external_id(external_id).await;
+        let advertiser =
+            get_advertiser_by_external_id(external_id, &RetryPolicy::default(), Environment::Dev)
+                .await;
         match advertiser {
             Ok(advertiser) => {
                 println!("Advertiser: {:?}", advertiser);
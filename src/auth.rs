@@ -0,0 +1,359 @@
+use crate::config::{AuthConfig, ServiceAuthConfig};
+use crate::error::TigerClawError;
+use crate::redact;
+use crate::secret::{SecretRef, SecretResolver};
+use crate::token::TokenManager;
+use log::warn;
+use reqwest::RequestBuilder;
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Service name for growth-account-migration-service
+pub const GROWTH_MIGRATION: &str = "growth_migration";
+/// Service name for the SAS data import service
+pub const SAS_DATA_IMPORT: &str = "sas_data_import";
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Adds credentials to requests for a single service
+pub trait AuthProvider: Send + Sync {
+    /// Returns the request with whatever credentials the service expects attached
+    fn authorize(&self, request: RequestBuilder) -> BoxFuture<'_, RequestBuilder>;
+
+    /// Called when the service rejected the credentials with a 401. Returns true
+    /// if the provider may now hand out different credentials, so retrying is
+    /// worthwhile
+    fn invalidate(&self) -> BoxFuture<'_, bool>;
+}
+
+/// Bearer token from the IdP client-credentials grant, refreshed near expiry
+pub struct ClientCredentials {
+    tokens: Arc<TokenManager>,
+}
+
+impl ClientCredentials {
+    pub fn new(tokens: Arc<TokenManager>) -> Self {
+        ClientCredentials { tokens }
+    }
+}
+
+impl AuthProvider for ClientCredentials {
+    fn authorize(&self, request: RequestBuilder) -> BoxFuture<'_, RequestBuilder> {
+        Box::pin(async move {
+            let token = self.tokens.token().await;
+            request.header("Authorization", format!("Bearer {}", token))
+        })
+    }
+
+    fn invalidate(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async move {
+            self.tokens.invalidate().await;
+            true
+        })
+    }
+}
+
+/// A fixed API key sent as-is in a header, the key never changes during a run
+pub struct StaticApiKey {
+    header: String,
+    key: String,
+}
+
+impl StaticApiKey {
+    pub fn new(header: &str, key: &str) -> Self {
+        redact::register_secret(key);
+        StaticApiKey {
+            header: header.to_string(),
+            key: key.to_string(),
+        }
+    }
+
+    /// Reads the key from an environment variable
+    pub fn from_env(header: &str, variable: &str) -> Result<Self, String> {
+        match env::var(variable) {
+            Ok(key) if !key.is_empty() => Ok(StaticApiKey::new(header, &key)),
+            _ => Err(format!(
+                "API key environment variable {} is not set",
+                variable
+            )),
+        }
+    }
+}
+
+impl AuthProvider for StaticApiKey {
+    fn authorize(&self, request: RequestBuilder) -> BoxFuture<'_, RequestBuilder> {
+        Box::pin(async move { request.header(self.header.as_str(), self.key.as_str()) })
+    }
+
+    fn invalidate(&self) -> BoxFuture<'_, bool> {
+        Box::pin(async { false })
+    }
+}
+
+/// A token issued outside of TigerClaw, e.g. copied from the migration UI. It is
+/// re-read on every request so a rotated token file is picked up mid run
+pub struct SourcedToken {
+    source: SecretRef,
+    scheme: String,
+    secrets: Arc<SecretResolver>,
+}
+
+impl SourcedToken {
+    pub fn new(source: SecretRef, scheme: &str) -> Self {
+        SourcedToken {
+            source,
+            scheme: scheme.to_string(),
+            secrets: Arc::new(SecretResolver::default()),
+        }
+    }
+
+    /// Resolves the token through `secrets`, so SSM parameters are shared with
+    /// the rest of the run
+    pub fn with_secrets(mut self, secrets: Arc<SecretResolver>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    async fn read(&self) -> Result<String, String> {
+        self.secrets
+            .resolve(&self.source)
+            .await
+            .map_err(|e| format!("Token {} could not be read: {}", self.source, e))
+    }
+}
+
+impl AuthProvider for SourcedToken {
+    fn authorize(&self, request: RequestBuilder) -> BoxFuture<'_, RequestBuilder> {
+        Box::pin(async move {
+            match self.read().await {
+                Ok(token) if self.scheme.is_empty() => request.header("Authorization", token),
+                Ok(token) => request.header("Authorization", format!("{} {}", self.scheme, token)),
+                Err(error) => {
+                    warn!("{}, sending request without credentials", error);
+                    request
+                }
+            }
+        })
+    }
+
+    fn invalidate(&self) -> BoxFuture<'_, bool> {
+        // Only a file or an SSM parameter can change underneath us during a run
+        let retry = matches!(self.source, SecretRef::File(_)) || self.secrets.forget(&self.source);
+        Box::pin(async move { retry })
+    }
+}
+
+/// Auth used by a service when the config has no `[auth.services.<name>]` entry
+fn default_service_auth(service: &str) -> Option<ServiceAuthConfig> {
+    match service {
+        GROWTH_MIGRATION => Some(ServiceAuthConfig::ClientCredentials),
+        SAS_DATA_IMPORT => Some(ServiceAuthConfig::ApiKey {
+            header: "Authorization".to_string(),
+            env: Some("AWIN_SAS_DATA_IMPORT_API_SECRET".to_string()),
+            secret: None,
+        }),
+        _ => None,
+    }
+}
+
+/// Builds the auth provider for each service from the `[auth.services]` config.
+/// Providers are created on first use so a missing credential only fails the
+/// services that actually need it. Secrets are read through the token
+/// manager's `SecretResolver`
+pub struct AuthProviders {
+    services: HashMap<String, ServiceAuthConfig>,
+    secrets: Arc<SecretResolver>,
+    tokens: Arc<TokenManager>,
+}
+
+impl AuthProviders {
+    pub fn new(auth_config: &AuthConfig, tokens: TokenManager) -> Self {
+        AuthProviders {
+            services: auth_config.services.clone(),
+            secrets: tokens.secrets(),
+            tokens: Arc::new(tokens),
+        }
+    }
+
+    /// Returns the auth provider configured for `service`
+    pub async fn get(&self, service: &str) -> Result<Arc<dyn AuthProvider>, TigerClawError> {
+        self.provider(service).await.map_err(TigerClawError::Auth)
+    }
+
+    async fn provider(&self, service: &str) -> Result<Arc<dyn AuthProvider>, String> {
+        let service_auth = self
+            .services
+            .get(service)
+            .cloned()
+            .or_else(|| default_service_auth(service))
+            .ok_or_else(|| {
+                format!(
+                    "No auth configured for service {}, add an [auth.services.{}] section",
+                    service, service
+                )
+            })?;
+
+        let secret = service_auth
+            .secret()
+            .map_err(|e| format!("{} for service {}", e, service))?;
+        let provider: Arc<dyn AuthProvider> = match (&service_auth, secret) {
+            (ServiceAuthConfig::ApiKey { header, .. }, Some(secret)) => {
+                let key = self
+                    .secrets
+                    .resolve(&secret)
+                    .await
+                    .map_err(|e| format!("API key {} could not be read: {}", secret, e))?;
+                Arc::new(StaticApiKey::new(header, &key))
+            }
+            (ServiceAuthConfig::Token { scheme, .. }, Some(secret)) => {
+                Arc::new(SourcedToken::new(secret, scheme).with_secrets(self.secrets.clone()))
+            }
+            _ => {
+                self.tokens.idp()?;
+                Arc::new(ClientCredentials::new(self.tokens.clone()))
+            }
+        };
+        Ok(provider)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::LocalParameterStore;
+    use crate::setup::{Environment, IdpSettings};
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    async fn authorization_header(provider: &dyn AuthProvider) -> Option<String> {
+        let request = provider
+            .authorize(reqwest::Client::new().get("http://localhost/"))
+            .await
+            .build()
+            .unwrap();
+        request
+            .headers()
+            .get("Authorization")
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    fn providers(auth_config: &AuthConfig) -> AuthProviders {
+        let idp = IdpSettings::resolve(&Environment::Dev, auth_config).unwrap();
+        AuthProviders::new(
+            auth_config,
+            TokenManager::new(Environment::Dev, idp, auth_config),
+        )
+    }
+
+    #[tokio::test]
+    async fn static_api_key_is_sent_raw() {
+        let provider = StaticApiKey::new("Authorization", "auth-test-api-key");
+        assert_eq!(
+            authorization_header(&provider).await.as_deref(),
+            Some("auth-test-api-key")
+        );
+        assert!(!provider.invalidate().await);
+    }
+
+    #[tokio::test]
+    async fn file_token_is_sent_with_scheme() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "auth-test-file-token").unwrap();
+        let provider = SourcedToken::new(SecretRef::File(file.path().to_path_buf()), "Bearer");
+        assert_eq!(
+            authorization_header(&provider).await.as_deref(),
+            Some("Bearer auth-test-file-token")
+        );
+        assert!(provider.invalidate().await);
+    }
+
+    #[tokio::test]
+    async fn services_are_configured_from_toml() {
+        let auth_config: AuthConfig = toml::from_str(
+            r#"
+            [services.reporting]
+            type = "token"
+            env = "TIGER_CLAW_REPORTING_TOKEN"
+
+            [services.sas_data_import]
+            type = "api_key"
+            header = "X-Api-Key"
+            env = "TIGER_CLAW_UNSET_API_KEY"
+            "#,
+        )
+        .unwrap();
+        let providers = providers(&auth_config);
+
+        assert!(providers.get("reporting").await.is_ok());
+        assert!(providers.get(GROWTH_MIGRATION).await.is_ok());
+        // configured, but the variable holding the key is missing
+        assert!(providers.get(SAS_DATA_IMPORT).await.is_err());
+        assert!(providers.get("unknown").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_missing_idp_only_fails_client_credentials() {
+        let auth_config: AuthConfig = toml::from_str(
+            r#"
+            [services.sas_data_import]
+            type = "api_key"
+            secret = "aws-ssm:/tiger-claw/sas-api-key"
+            "#,
+        )
+        .unwrap();
+        let parameters = LocalParameterStore::new([("/tiger-claw/sas-api-key", "auth-test-key")]);
+        let tokens = TokenManager::for_environment(Environment::Staging, &auth_config)
+            .with_secrets(Arc::new(SecretResolver::new(Arc::new(parameters))));
+        let providers = AuthProviders::new(&auth_config, tokens);
+
+        assert!(providers.get(SAS_DATA_IMPORT).await.is_ok());
+        let error = providers.get(GROWTH_MIGRATION).await.err().unwrap();
+        assert!(
+            error
+                .to_string()
+                .contains("No IdP URL configured for staging"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn secrets_are_read_from_ssm() {
+        let auth_config: AuthConfig = toml::from_str(
+            r#"
+            [services.sas_data_import]
+            type = "api_key"
+            secret = "aws-ssm:/tiger-claw/sas-api-key"
+
+            [services.reporting]
+            type = "token"
+            secret = "aws-ssm:/tiger-claw/reporting-token"
+            "#,
+        )
+        .unwrap();
+        let parameters = LocalParameterStore::new([
+            ("/tiger-claw/sas-api-key", "auth-test-ssm-api-key"),
+            ("/tiger-claw/reporting-token", "auth-test-ssm-token"),
+        ]);
+        let idp = IdpSettings::resolve(&Environment::Dev, &auth_config).unwrap();
+        let tokens = TokenManager::new(Environment::Dev, idp, &auth_config)
+            .with_secrets(Arc::new(SecretResolver::new(Arc::new(parameters))));
+        let providers = AuthProviders::new(&auth_config, tokens);
+
+        let api_key = providers.get(SAS_DATA_IMPORT).await.unwrap();
+        assert_eq!(
+            authorization_header(api_key.as_ref()).await.as_deref(),
+            Some("auth-test-ssm-api-key")
+        );
+        let token = providers.get("reporting").await.unwrap();
+        assert_eq!(
+            authorization_header(token.as_ref()).await.as_deref(),
+            Some("Bearer auth-test-ssm-token")
+        );
+        // A rejected SSM token is fetched again
+        assert!(token.invalidate().await);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::error::TigerClawError;
+use crate::setup::Environment;
+use aws_sdk_dynamodb::Client;
+use log::debug;
+use std::env;
+
+/// The AWS profile local and dev read DynamoDB with when `AWS_PROFILE` isn't
+/// set. Staging and production have no default, they need `AWS_PROFILE`
+pub const DEFAULT_PROFILE: &str = "org-adm-springfield-dev-poweruser";
+
+/// The AWS profile DynamoDB is read with in `environment`
+pub fn profile(environment: Environment) -> Result<String, TigerClawError> {
+    profile_from(environment, env::var("AWS_PROFILE").ok())
+}
+
+fn profile_from(
+    environment: Environment,
+    aws_profile: Option<String>,
+) -> Result<String, TigerClawError> {
+    match aws_profile.filter(|profile| !profile.trim().is_empty()) {
+        Some(profile) => Ok(profile),
+        None if matches!(environment, Environment::Local | Environment::Dev) => {
+            Ok(DEFAULT_PROFILE.to_string())
+        }
+        None => Err(TigerClawError::Config(format!(
+            "AWS_PROFILE isn't set, set it to the {} AWS profile to use DynamoDB",
+            environment.name()
+        ))),
+    }
+}
+
+/// DynamoDB client for the AWS profile of `environment`
+pub async fn dynamo_db_client(environment: Environment) -> Result<Client, TigerClawError> {
+    let profile = profile(environment)?;
+    debug!("Connecting to DynamoDB with AWS profile {}", profile);
+    let config = aws_config::from_env().profile_name(profile).load().await;
+    Ok(Client::new(&config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unset_or_empty_aws_profile_uses_the_default_in_local_and_dev() {
+        for environment in [Environment::Local, Environment::Dev] {
+            assert_eq!(profile_from(environment, None).unwrap(), DEFAULT_PROFILE);
+            assert_eq!(
+                profile_from(environment, Some(" ".to_string())).unwrap(),
+                DEFAULT_PROFILE
+            );
+        }
+        assert_eq!(
+            profile_from(Environment::Dev, Some("org-adm-springfield-qa".to_string())).unwrap(),
+            "org-adm-springfield-qa"
+        );
+    }
+
+    #[test]
+    fn staging_and_production_need_aws_profile() {
+        for environment in [Environment::Staging, Environment::Production] {
+            let error = profile_from(environment, None).unwrap_err();
+            assert!(matches!(error, TigerClawError::Config(_)));
+            assert!(error.to_string().contains("AWS_PROFILE isn't set"));
+        }
+        assert_eq!(
+            profile_from(
+                Environment::Staging,
+                Some("org-adm-springfield-staging".to_string())
+            )
+            .unwrap(),
+            "org-adm-springfield-staging"
+        );
+    }
+}
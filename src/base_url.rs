@@ -0,0 +1,103 @@
+use reqwest::Url;
+
+/// Resolves a service's base URL from config.
+///
+/// `url` is the new single field, e.g. `https://host:8443/api`. When it isn't
+/// set the old `base_*_url` and `base_*_port` pair is used instead: the port is
+/// applied unless the old URL already carries one
+pub fn resolve(
+    service: &str,
+    url: Option<&str>,
+    legacy_url: Option<&str>,
+    legacy_port: Option<i32>,
+) -> Result<Url, String> {
+    if let Some(url) = url.filter(|url| !url.trim().is_empty()) {
+        return parse(service, url);
+    }
+
+    let legacy_url = legacy_url
+        .filter(|url| !url.trim().is_empty())
+        .ok_or_else(|| format!("No base URL configured for {}", service))?;
+    let mut base = parse(service, legacy_url)?;
+    if let Some(port) = legacy_port
+        && base.port().is_none()
+    {
+        let port = u16::try_from(port)
+            .ok()
+            .filter(|port| *port > 0)
+            .ok_or_else(|| format!("Invalid port {} for {}, must be 1-65535", port, service))?;
+        base.set_port(Some(port))
+            .map_err(|_| format!("Cannot set a port on the {} base URL {}", service, base))?;
+    }
+    Ok(base)
+}
+
+fn parse(service: &str, url: &str) -> Result<Url, String> {
+    let mut base = Url::parse(url.trim())
+        .map_err(|e| format!("Invalid base URL for {} '{}': {}", service, url, e))?;
+    if !matches!(base.scheme(), "http" | "https") {
+        return Err(format!(
+            "Invalid base URL for {} '{}': scheme must be http or https",
+            service, url
+        ));
+    }
+    // Without a trailing slash `join` would replace the last path segment
+    if !base.path().ends_with('/') {
+        let path = format!("{}/", base.path());
+        base.set_path(&path);
+    }
+    Ok(base)
+}
+
+/// Appends `endpoint_path` to the base URL, keeping any path the base already has
+pub fn join(base: &Url, endpoint_path: &str) -> Url {
+    let mut url = base.clone();
+    let path = format!("{}{}", base.path(), endpoint_path.trim_start_matches('/'));
+    url.set_path(&path);
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_url_and_port_are_combined() {
+        let base = resolve("test", None, Some("http://localhost"), Some(8080)).unwrap();
+        assert_eq!(
+            join(&base, "merchant/1").as_str(),
+            "http://localhost:8080/merchant/1"
+        );
+    }
+
+    #[test]
+    fn default_https_port_is_not_duplicated() {
+        let base = resolve("test", None, Some("https://host.example.com"), Some(443)).unwrap();
+        assert_eq!(join(&base, "/x").as_str(), "https://host.example.com/x");
+    }
+
+    #[test]
+    fn explicit_port_in_legacy_url_wins() {
+        let base = resolve("test", None, Some("http://localhost:9000/"), Some(8080)).unwrap();
+        assert_eq!(base.as_str(), "http://localhost:9000/");
+    }
+
+    #[test]
+    fn new_url_keeps_its_path() {
+        let base = resolve(
+            "test",
+            Some("https://host/api"),
+            Some("http://ignored"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(join(&base, "status").as_str(), "https://host/api/status");
+    }
+
+    #[test]
+    fn missing_or_malformed_urls_fail() {
+        assert!(resolve("test", None, Some(""), None).is_err());
+        assert!(resolve("test", Some("localhost:8080"), None, None).is_err());
+        assert!(resolve("test", None, Some("http://localhost"), Some(70000)).is_err());
+    }
+}
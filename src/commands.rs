@@ -0,0 +1,366 @@
+use crate::advertiser;
+use crate::auth::{AuthProviders, GROWTH_MIGRATION, SAS_DATA_IMPORT};
+use crate::config::TestConfig;
+use crate::error::TigerClawError;
+use crate::growth_migration::GrowthMigrationClient;
+use crate::guardrails::Guardrails;
+use crate::health::{Service, ServiceHealth};
+use crate::orchestration_status::{MigrationStatus, StepDescriptor};
+use crate::orchestrator;
+use crate::retry::RetryPolicy;
+use crate::sas_data_import;
+use crate::sas_data_import_client::SasDataImportClient;
+use crate::sas_migration_api;
+use crate::terms_and_conditions;
+use clap::Subcommand;
+use log::{error, info};
+use std::path::PathBuf;
+
+/// What the binary does. Without a subcommand every suite enabled in the
+/// config is run
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Run every suite enabled in the config
+    Run,
+    /// Run orchestration steps for the configured external ID
+    Step {
+        /// Steps to run, e.g. VALID, SF,ADV or VALID..FEE
+        step: String,
+    },
+    /// Show the migration record of a SAS advertiser
+    Status {
+        /// SAS external ID of the advertiser
+        external_id: i32,
+    },
+    /// Force the migration status of a SAS advertiser
+    ForceStatus {
+        /// SAS external ID of the advertiser
+        external_id: i32,
+        /// Status to force, e.g. INIT_DONE
+        status: String,
+    },
+    /// Fetch and validate the terms and conditions of an Awin advertiser
+    Terms {
+        /// Awin advertiser ID
+        advertiser_id: i32,
+    },
+    /// Fetch a merchant from the SAS data import service
+    Merchant {
+        /// SAS merchant ID
+        merchant_id: i32,
+    },
+    /// Lock a SAS merchant down and lock its fees
+    Lockdown {
+        /// SAS merchant ID
+        merchant_id: i32,
+    },
+    /// Lift the lockdown and fee lock of a SAS merchant
+    Unlock {
+        /// SAS merchant ID
+        merchant_id: i32,
+    },
+    /// Check every service the suites use is up, fails if any isn't
+    Health,
+    /// Work with the config file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum ConfigCommand {
+    /// Load and validate the config file without contacting any service
+    Check,
+    /// Print the merged config and where each value came from
+    Show,
+    /// Write a commented starter config for the environment given with --env
+    Init {
+        /// Where to write it, defaults to tigerclaw.<environment>.toml
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Replace the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Runs a one-off operational command. `Run` and `Config` are handled by the
+/// binary, which runs the configured suites or never needs credentials
+pub async fn run(
+    command: &Command,
+    mut config: TestConfig,
+    auth_providers: &AuthProviders,
+    retry: &RetryPolicy,
+    guardrails: &Guardrails,
+) -> Result<(), TigerClawError> {
+    match command {
+        Command::Step { step } => {
+            StepDescriptor::parse_list(step).map_err(TigerClawError::Config)?;
+            config.orchestration.step_to_run = step.to_uppercase();
+            config.orchestration.force_run = false;
+            let client =
+                growth_migration_client(&config, auth_providers, retry, guardrails).await?;
+            orchestrator::run(&client, &config, retry, guardrails.environment()).await
+        }
+        Command::Status { external_id } => {
+            if guardrails.dry_run() {
+                info!(
+                    "Dry run, sending {}",
+                    advertiser::describe_lookup(*external_id)
+                );
+            }
+            let advertiser = advertiser::get_advertiser_by_external_id(
+                *external_id,
+                retry,
+                guardrails.environment(),
+            )
+            .await?;
+            info!(
+                "Advertiser {} (Awin {}): {}, completed: {}, started: {}, ended: {}",
+                advertiser.external_id,
+                advertiser.awin_id,
+                advertiser.migration_status_string,
+                advertiser.migration_completed,
+                format_date(advertiser.start_date),
+                format_date(advertiser.end_date),
+            );
+            Ok(())
+        }
+        Command::ForceStatus {
+            external_id,
+            status,
+        } => {
+            if MigrationStatus::from_string(status, StepDescriptor::Init).is_none() {
+                return Err(TigerClawError::Config(format!(
+                    "Unknown migration status: {}",
+                    status
+                )));
+            }
+            let client =
+                growth_migration_client(&config, auth_providers, retry, guardrails).await?;
+            client
+                .force_status(*external_id, &status.to_uppercase())
+                .await?;
+            info!(
+                "Forced the migration status of advertiser {} to {}",
+                external_id,
+                status.to_uppercase()
+            );
+            Ok(())
+        }
+        Command::Terms { advertiser_id } => {
+            let client =
+                growth_migration_client(&config, auth_providers, retry, guardrails).await?;
+            terms_and_conditions::run(&client, advertiser_id).await
+        }
+        Command::Merchant { merchant_id } => {
+            let client = sas_data_import_client(&config, auth_providers, retry, guardrails).await?;
+            sas_data_import::merchant_extraction(&client, *merchant_id)
+                .await
+                .map(|_| ())
+        }
+        Command::Lockdown { merchant_id } => {
+            let client =
+                growth_migration_client(&config, auth_providers, retry, guardrails).await?;
+            let merchants = lock_verifier(&config, auth_providers, retry, guardrails).await?;
+            sas_migration_api::run(merchant_id, &client, &merchants).await
+        }
+        Command::Unlock { merchant_id } => {
+            let client =
+                growth_migration_client(&config, auth_providers, retry, guardrails).await?;
+            let merchants = lock_verifier(&config, auth_providers, retry, guardrails).await?;
+            sas_migration_api::unlock(merchant_id, &client, &merchants).await
+        }
+        Command::Health => {
+            let health = ServiceHealth::check_services(&Service::ALL, |service| {
+                probe(service, &config, auth_providers, retry, guardrails)
+            })
+            .await;
+            info!("{}", health.summary());
+            health.require_all_up()
+        }
+        Command::Run | Command::Config(_) => Err(TigerClawError::Config(format!(
+            "{:?} is not a one-off command",
+            command
+        ))),
+    }
+}
+
+/// Client for growth-account-migration-service with the run's auth, timeouts,
+/// retry policy and guardrails
+pub async fn growth_migration_client(
+    config: &TestConfig,
+    auth_providers: &AuthProviders,
+    retry: &RetryPolicy,
+    guardrails: &Guardrails,
+) -> Result<GrowthMigrationClient, TigerClawError> {
+    let client =
+        GrowthMigrationClient::new(&config.globals, auth_providers.get(GROWTH_MIGRATION).await?)?
+            .with_timeouts(config.timeouts.for_service(GROWTH_MIGRATION))?
+            .with_retry(retry.clone())
+            .with_guardrails(guardrails.clone());
+    Ok(client)
+}
+
+/// Checks a service is up. Building the client can fail on auth or config,
+/// which `ServiceHealth` tells apart from the service being down
+pub async fn probe(
+    service: Service,
+    config: &TestConfig,
+    auth_providers: &AuthProviders,
+    retry: &RetryPolicy,
+    guardrails: &Guardrails,
+) -> Result<(), TigerClawError> {
+    match service {
+        Service::GrowthMigration => {
+            growth_migration_client(config, auth_providers, retry, guardrails)
+                .await?
+                .health()
+                .await
+        }
+        Service::SasDataImport => {
+            let client = sas_data_import_client(config, auth_providers, retry, guardrails).await?;
+            sas_data_import::health_check(&client).await
+        }
+        Service::DynamoDb => {
+            if guardrails.dry_run() {
+                info!("Dry run, sending DynamoDB DescribeTable to check it is reachable");
+            }
+            advertiser::check_table(retry, guardrails.environment()).await
+        }
+    }
+}
+
+/// Client for the SAS data import service with the run's auth, timeouts and
+/// retry policy. It only reads, the guardrails just say whether this is a dry run
+pub async fn sas_data_import_client(
+    config: &TestConfig,
+    auth_providers: &AuthProviders,
+    retry: &RetryPolicy,
+    guardrails: &Guardrails,
+) -> Result<SasDataImportClient, TigerClawError> {
+    let client =
+        SasDataImportClient::new(&config.globals, auth_providers.get(SAS_DATA_IMPORT).await?)?
+            .with_timeouts(config.timeouts.for_service(SAS_DATA_IMPORT))?
+            .with_retry(retry.clone())
+            .with_dry_run(guardrails.dry_run())
+            .with_endpoints(&config.sas_data_import.endpoints);
+    Ok(client)
+}
+
+/// SAS data import client to read merchants back after lock requests. Built
+/// before any lock is sent, locks that can't be verified fail the command
+pub async fn lock_verifier(
+    config: &TestConfig,
+    auth_providers: &AuthProviders,
+    retry: &RetryPolicy,
+    guardrails: &Guardrails,
+) -> Result<SasDataImportClient, TigerClawError> {
+    sas_data_import_client(config, auth_providers, retry, guardrails)
+        .await
+        .inspect_err(|error| error!("Merchant locks can't be verified: {}", error))
+}
+
+fn format_date(date: Option<chrono::DateTime<chrono::Utc>>) -> String {
+    date.map(|date| date.to_rfc3339())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setup::{Args, Environment, IdpSettings};
+    use crate::token::TokenManager;
+    use clap::Parser;
+
+    #[test]
+    fn subcommands_parse_after_global_options() {
+        let args = Args::parse_from([
+            "tiger-claw",
+            "-n",
+            "dev",
+            "force-status",
+            "44911",
+            "INIT_DONE",
+            "--read-only",
+        ]);
+        assert_eq!(
+            args.command,
+            Some(Command::ForceStatus {
+                external_id: 44911,
+                status: "INIT_DONE".to_string(),
+            })
+        );
+        assert!(args.read_only);
+
+        let args = Args::parse_from(["tiger-claw", "-n", "dev", "config", "check"]);
+        assert_eq!(args.command, Some(Command::Config(ConfigCommand::Check)));
+
+        let args = Args::parse_from(["tiger-claw", "config", "init", "--env", "dev"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Config(ConfigCommand::Init {
+                output: None,
+                force: false,
+            }))
+        );
+        assert_eq!(args.environment().unwrap(), Environment::Dev);
+
+        let args = Args::parse_from(["tiger-claw", "-n", "dev"]);
+        assert_eq!(args.command, None);
+    }
+
+    fn config() -> TestConfig {
+        toml::from_str(
+            r#"
+            [orchestration]
+            enabled = false
+            step_to_run = ""
+            step_status_to_force = ""
+            force_run = false
+            [sas_data_import]
+            enabled = false
+            [dynamo_db]
+            enabled = false
+            [globals]
+            environment = "local"
+            external_id = 1
+            [migration_api]
+            enabled = false
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn invalid_step_and_status_are_rejected_before_any_request() {
+        let config = config();
+        let idp = IdpSettings::resolve(&Environment::Local, &config.auth).unwrap();
+        let tokens = TokenManager::new(Environment::Local, idp, &config.auth);
+        let auth_providers = AuthProviders::new(&config.auth, tokens);
+        let retry = RetryPolicy::none();
+        let guardrails = Guardrails::local(false);
+
+        let step = Command::Step {
+            step: "NOPE".to_string(),
+        };
+        assert!(matches!(
+            run(&step, config, &auth_providers, &retry, &guardrails).await,
+            Err(TigerClawError::Config(_))
+        ));
+        let force_status = Command::ForceStatus {
+            external_id: 1,
+            status: "DONE_ISH".to_string(),
+        };
+        assert!(matches!(
+            run(
+                &force_status,
+                self::config(),
+                &auth_providers,
+                &retry,
+                &guardrails
+            )
+            .await,
+            Err(TigerClawError::Config(_))
+        ));
+    }
+}
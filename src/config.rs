@@ -1,32 +1,224 @@
-use serde::Deserialize;
+use crate::base_url;
+use crate::config_check::{self, Diagnostic, KeyLines};
+use crate::error::TigerClawError;
+use crate::orchestration_status::{MigrationStatus, StepDescriptor};
+use crate::retry::RetryConfig;
+use crate::sas_resources::SasResource;
+use crate::secret::SecretRef;
+use crate::setup::Environment;
+use crate::suites::{Suite, SuitePlan};
+use crate::timeouts::TimeoutConfig;
+use log::warn;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use toml;
 
 /// Configuration for the tests to run
 ///
-/// This is a TOML file that specifies which tests to run.
+/// This is a TOML file that specifies which tests to run, layered on an
+/// optional base file and `TIGERCLAW_*` environment variables, see
+/// `LayeredConfig`. Every section is optional and falls back to its defaults.
 ///
-/// The file should contain the following sections:
+/// The sections are:
 /// - [orchestration]: settings for orchestrator tests
 /// - [sas_data_import]: settings for SAS data import tests
 /// - [dynamo_db]: settings for DynamoDB tests
 /// - [globals]: global settings like force execution
-#[derive(Debug, Deserialize)]
+/// - [auth]: optional, IdP settings, token caching and per service auth
+/// - [guardrails]: optional, the advertisers mutating calls may change
+/// - [suites]: optional, the order suites run in and their dependencies
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct TestConfig {
     pub orchestration: OrchestrationConfig,
     pub sas_data_import: SasDataImportConfig,
     pub dynamo_db: DynamoDbConfig,
     pub globals: GlobalConfig,
     pub migration_api: MigrationApiConfig,
+    pub terms: TermsConfig,
+    pub auth: AuthConfig,
+    pub retry: RetryConfig,
+    pub timeouts: TimeoutConfig,
+    pub guardrails: GuardrailConfig,
+    pub suites: SuiteConfig,
 }
 
-#[derive(Debug, Deserialize)]
+/// Controls where access tokens come from and how they are cached between
+/// requests and runs
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Token endpoint of the IdP, required for staging and production
+    pub idp_url: Option<String>,
+    /// Client ID used for the client-credentials grant
+    pub client_id: Option<String>,
+    /// Name of the environment variable holding the client secret
+    pub client_secret_env: Option<String>,
+    /// Where the client secret is read from instead, e.g.
+    /// `aws-ssm:/springfield/dev/client-secret`
+    pub client_secret: Option<SecretRef>,
+    /// Persist the token to a user-local file keyed by environment so
+    /// consecutive runs can reuse it
+    pub cache_token_file: bool,
+    /// Refresh the token when it has fewer than this many seconds left
+    pub refresh_margin_seconds: i64,
+    /// How each service authenticates, keyed by service name. Services without
+    /// an entry use their built in default
+    pub services: HashMap<String, ServiceAuthConfig>,
+}
+
+/// Authentication for a single service, e.g.
+///
+/// ```toml
+/// [auth.services.sas_data_import]
+/// type = "api_key"
+/// header = "Authorization"
+/// env = "AWIN_SAS_DATA_IMPORT_API_SECRET"
+/// ```
+///
+/// `secret` takes a reference to any secret source instead of an environment
+/// variable name, e.g. `secret = "aws-ssm:/springfield/dev/sas-api-key"`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServiceAuthConfig {
+    /// Bearer token from the IdP configured in `[auth]`
+    ClientCredentials,
+    /// Key read from the `env` variable or `secret` and sent as-is in `header`
+    ApiKey {
+        #[serde(default = "default_api_key_header")]
+        header: String,
+        env: Option<String>,
+        secret: Option<SecretRef>,
+    },
+    /// Pre-issued token read from either the `env` variable, a `file` or
+    /// `secret`
+    Token {
+        env: Option<String>,
+        file: Option<PathBuf>,
+        secret: Option<SecretRef>,
+        #[serde(default = "default_token_scheme")]
+        scheme: String,
+    },
+}
+
+impl ServiceAuthConfig {
+    /// Where the credential is read from, `None` for client credentials which
+    /// use the IdP token
+    pub fn secret(&self) -> Result<Option<SecretRef>, String> {
+        match self {
+            ServiceAuthConfig::ClientCredentials => Ok(None),
+            ServiceAuthConfig::ApiKey { env, secret, .. } => match (env, secret) {
+                (Some(env), None) => Ok(Some(SecretRef::Env(env.clone()))),
+                (None, Some(secret)) => Ok(Some(secret.clone())),
+                _ => Err("API key auth needs exactly one of env or secret".to_string()),
+            },
+            ServiceAuthConfig::Token {
+                env, file, secret, ..
+            } => match (env, file, secret) {
+                (Some(env), None, None) => Ok(Some(SecretRef::Env(env.clone()))),
+                (None, Some(file), None) => Ok(Some(SecretRef::File(file.clone()))),
+                (None, None, Some(secret)) => Ok(Some(secret.clone())),
+                _ => Err("Token auth needs exactly one of env, file or secret".to_string()),
+            },
+        }
+    }
+}
+
+fn default_api_key_header() -> String {
+    "Authorization".to_string()
+}
+
+fn default_token_scheme() -> String {
+    "Bearer".to_string()
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            idp_url: None,
+            client_id: None,
+            client_secret_env: None,
+            client_secret: None,
+            cache_token_file: false,
+            refresh_margin_seconds: 60,
+            services: HashMap::new(),
+        }
+    }
+}
+
+/// Test advertisers that mutating calls may change, keyed by environment name,
+/// e.g.
+///
+/// ```toml
+/// [guardrails.allowed_external_ids]
+/// staging = [242424]
+/// ```
+///
+/// Environments without an entry allow any ID, see `Guardrails`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GuardrailConfig {
+    /// SAS external IDs, which are also the SAS merchant IDs
+    pub allowed_external_ids: HashMap<String, Vec<i32>>,
+    /// Awin advertiser IDs
+    pub allowed_advertiser_ids: HashMap<String, Vec<i32>>,
+}
+
+/// Order the suites run in and which have to pass before another runs, e.g.
+///
+/// ```toml
+/// [suites]
+/// order = ["sas_data_import", "orchestration", "migration_api"]
+/// depends_on.migration_api = ["orchestration"]
+/// ```
+///
+/// A suite is skipped when one of its dependencies failed or was skipped,
+/// see `SuitePlan`. With `concurrency` above 1 suites that don't depend on
+/// each other run at the same time
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SuiteConfig {
+    /// Suite names, suites left out run after the listed ones
+    pub order: Vec<String>,
+    /// Suite name to the suites that have to pass before it runs
+    pub depends_on: HashMap<String, Vec<String>>,
+    /// How many suites may run at once, 1 runs them one after another
+    pub concurrency: usize,
+    /// Probe the services first and skip the suites that need one that is
+    /// down, see `ServiceHealth`
+    pub health_checks: bool,
+}
+
+impl Default for SuiteConfig {
+    fn default() -> Self {
+        SuiteConfig {
+            order: Suite::ALL
+                .iter()
+                .map(|suite| suite.name().to_string())
+                .collect(),
+            depends_on: HashMap::new(),
+            concurrency: 1,
+            health_checks: true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct MigrationApiConfig {
     pub enabled: bool,
+    /// Lift both locks again once they are verified, so the test merchant is
+    /// back to normal after the run
+    pub unlock_after_run: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct OrchestrationConfig {
     pub enabled: bool,
     pub step_to_run: String,
@@ -34,89 +226,690 @@ pub struct OrchestrationConfig {
     pub force_run: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct SasDataImportConfig {
     pub enabled: bool,
+    /// Resources to pull, e.g. `["merchant", "vouchers"]`. Defaults to the
+    /// merchant and the source data of `orchestration.step_to_run`
+    pub resources: Vec<String>,
+    /// Resource name to the path it is read from, replacing
+    /// `SasResource::default_path`, e.g. `vouchers = "merchant/{merchant_id}/codes"`
+    pub endpoints: HashMap<String, String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct DynamoDbConfig {
+    /// Read the advertiser record of `globals.external_id` and check it
     pub enabled: bool,
 }
 
-#[derive(Debug, Deserialize, Default)]
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TermsConfig {
+    /// Fetch and validate the terms and conditions of `globals.advertiser_id`
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct GlobalConfig {
     pub advertiser_id: Option<i32>,
     pub external_id: Option<i32>,
     pub migration_name: Option<String>,
     pub environment: String,
-    pub base_growth_migration_url: String,
-    pub base_growth_migration_port: i32,
-    pub base_sas_data_import_url: String,
-    pub base_sas_data_import_port: i32,
+    /// Base URL of growth-account-migration-service, including the port if it
+    /// isn't the scheme default
+    pub growth_migration_url: Option<String>,
+    /// Base URL of the SAS data import service
+    pub sas_data_import_url: Option<String>,
+    /// Deprecated, use `growth_migration_url`. Still read when it isn't set
+    pub base_growth_migration_url: Option<String>,
+    pub base_growth_migration_port: Option<i32>,
+    /// Deprecated, use `sas_data_import_url`. Still read when it isn't set
+    pub base_sas_data_import_url: Option<String>,
+    pub base_sas_data_import_port: Option<i32>,
+    /// Refuse every mutating call, production only runs in this mode
+    pub read_only: bool,
 }
 
 impl GlobalConfig {
+    /// Base URL of growth-account-migration-service, falling back to the old
+    /// `base_growth_migration_url` and `base_growth_migration_port` fields
+    pub fn growth_migration_base_url(&self) -> Result<Url, String> {
+        base_url::resolve(
+            "growth_migration",
+            self.growth_migration_url.as_deref(),
+            self.base_growth_migration_url.as_deref(),
+            self.base_growth_migration_port,
+        )
+    }
+
+    /// Base URL of the SAS data import service, falling back to the old
+    /// `base_sas_data_import_url` and `base_sas_data_import_port` fields
+    pub fn sas_data_import_base_url(&self) -> Result<Url, String> {
+        base_url::resolve(
+            "sas_data_import",
+            self.sas_data_import_url.as_deref(),
+            self.base_sas_data_import_url.as_deref(),
+            self.base_sas_data_import_port,
+        )
+    }
+
+    /// The first problem with the globals, see `problems`
     pub fn validate(&self) -> Result<(), String> {
+        match self.problems().into_iter().next() {
+            Some(problem) => Err(problem.message),
+            None => Ok(()),
+        }
+    }
+
+    /// Every problem with the IDs, names, URLs and ports in `[globals]`
+    pub fn problems(&self) -> Vec<Diagnostic> {
+        let mut problems = Vec::new();
         // Validate advertiser_id > 0 if present
-        if let Some(id) = self.advertiser_id {
-            if id <= 0 {
-                return Err(format!(
-                    "Invalid advertiser_id: {}. Must be greater than 0",
-                    id
-                ));
-            }
+        if let Some(id) = self.advertiser_id
+            && id <= 0
+        {
+            problems.push(Diagnostic::for_key(
+                "globals.advertiser_id",
+                format!("Invalid advertiser_id: {}. Must be greater than 0", id),
+            ));
         }
 
         // Validate external_id > 0 if present
-        if let Some(id) = self.external_id {
-            if id <= 0 {
-                return Err(format!(
-                    "Invalid external_id: {}. Must be greater than 0",
-                    id
-                ));
-            }
+        if let Some(id) = self.external_id
+            && id <= 0
+        {
+            problems.push(Diagnostic::for_key(
+                "globals.external_id",
+                format!("Invalid external_id: {}. Must be greater than 0", id),
+            ));
         }
 
         // Validate migration_name is "sas" case insensitive if present
-        if let Some(name) = &self.migration_name {
-            if name.to_lowercase() != "sas" {
-                return Err(format!(
+        if let Some(name) = &self.migration_name
+            && name.to_lowercase() != "sas"
+        {
+            problems.push(Diagnostic::for_key(
+                "globals.migration_name",
+                format!(
                     "Invalid migration_name: {}. Must be 'sas' (case insensitive)",
                     name
+                ),
+            ));
+        }
+
+        if !self.environment.is_empty() && Environment::from_name(&self.environment).is_none() {
+            problems.push(Diagnostic::for_key(
+                "globals.environment",
+                format!(
+                    "Invalid environment: {}. Must be local, dev, staging or production",
+                    self.environment
+                ),
+            ));
+        }
+
+        let urls = [
+            ("growth_migration_url", &self.growth_migration_url),
+            ("sas_data_import_url", &self.sas_data_import_url),
+            ("base_growth_migration_url", &self.base_growth_migration_url),
+            ("base_sas_data_import_url", &self.base_sas_data_import_url),
+        ];
+        for (field, url) in urls {
+            if let Some(url) = url.as_deref().filter(|url| !url.trim().is_empty())
+                && let Err(error) = base_url::resolve(field, Some(url), None, None)
+            {
+                problems.push(Diagnostic::for_key(&format!("globals.{}", field), error));
+            }
+        }
+
+        let ports = [
+            (
+                "base_growth_migration_port",
+                self.base_growth_migration_port,
+            ),
+            ("base_sas_data_import_port", self.base_sas_data_import_port),
+        ];
+        for (field, port) in ports {
+            if let Some(port) = port
+                && !(1..=65535).contains(&port)
+            {
+                problems.push(Diagnostic::for_key(
+                    &format!("globals.{}", field),
+                    format!("Invalid {}: {}. Must be 1-65535", field, port),
                 ));
             }
         }
 
-        Ok(())
+        problems
+    }
+}
+
+pub fn load_test_config(config_path: &str) -> Result<TestConfig, TigerClawError> {
+    LayeredConfig::load(config_path).map(|layered| layered.config)
+}
+
+/// Prefix of the environment variables that override config values, e.g.
+/// `TIGERCLAW_GLOBALS_EXTERNAL_ID` for `globals.external_id`
+pub const ENV_PREFIX: &str = "TIGERCLAW_";
+
+/// Where a configuration value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::Env(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A `TestConfig` merged from its layers, lowest first:
+///
+/// 1. defaults for everything left out
+/// 2. the base file the config file is an overlay of, `tests.toml` for
+///    `tests.dev.toml`, if it exists
+/// 3. the config file itself
+/// 4. `TIGERCLAW_*` environment variables
+///
+/// Tables are merged key by key, any other value replaces the one below it
+///
+/// Loading fails with every unknown key and mistyped value in any layer,
+/// `validate` then reports every invalid value. Both point at the file and line
+/// or the environment variable the value came from
+#[derive(Debug)]
+pub struct LayeredConfig {
+    pub config: TestConfig,
+    sources: BTreeMap<String, ConfigSource>,
+    layers: Vec<Layer>,
+}
+
+/// One config file
+#[derive(Debug)]
+struct Layer {
+    path: PathBuf,
+    table: toml::Table,
+    lines: KeyLines,
+}
+
+impl Layer {
+    fn location(&self, key: &str) -> String {
+        match self.lines.line_of(key) {
+            Some(line) => format!("{}:{}", self.path.display(), line),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+impl LayeredConfig {
+    pub fn load(config_path: &str) -> Result<Self, TigerClawError> {
+        let vars = env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        });
+        Self::load_with_env(config_path, vars)
+    }
+
+    fn load_with_env(
+        config_path: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, TigerClawError> {
+        let path = Path::new(config_path);
+        if !path.exists() {
+            return Err(TigerClawError::Config(format!(
+                "Configuration file not found: {}",
+                config_path
+            )));
+        }
+
+        let paths = base_config_path(path)
+            .filter(|base| base.exists())
+            .into_iter()
+            .chain([path.to_path_buf()]);
+        let mut layers = Vec::new();
+        for path in paths {
+            let contents = fs::read_to_string(&path).map_err(|e| {
+                TigerClawError::Config(format!(
+                    "Failed to read configuration file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            let table: toml::Table = toml::from_str(&contents).map_err(|e| {
+                TigerClawError::Config(format!(
+                    "Failed to parse configuration file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            layers.push(Layer {
+                path,
+                table,
+                lines: KeyLines::parse(&contents),
+            });
+        }
+
+        let mut merged = toml::Table::new();
+        let mut sources = BTreeMap::new();
+        for layer in &layers {
+            let source = ConfigSource::File(layer.path.clone());
+            merge(&mut merged, layer.table.clone(), "", &source, &mut sources);
+        }
+        apply_env(&mut merged, vars, &mut sources)?;
+
+        let diagnostics = schema_diagnostics(&layers, &merged, &sources);
+        if !diagnostics.is_empty() {
+            return Err(TigerClawError::Config(format!(
+                "Invalid configuration, {}",
+                config_check::report(&diagnostics)
+            )));
+        }
+
+        let config = toml::Value::Table(merged)
+            .try_into()
+            .map_err(|e| TigerClawError::Config(format!("Invalid configuration: {}", e)))?;
+        Ok(LayeredConfig {
+            config,
+            sources,
+            layers,
+        })
+    }
+
+    /// Checks every value of the merged config, see `validate_test_config`
+    pub fn validate(&self) -> Result<(), TigerClawError> {
+        let diagnostics: Vec<_> = problems(&self.config)
+            .into_iter()
+            .map(|problem| {
+                let location = problem.key.as_deref().and_then(|key| self.location(key));
+                problem.at(location)
+            })
+            .collect();
+        into_result(&diagnostics)
+    }
+
+    /// Where the value at `key` was set. Defaults point at the closest table
+    /// they belong in, in the last file that has it
+    fn location(&self, key: &str) -> Option<String> {
+        match self.source(key) {
+            ConfigSource::File(path) => self
+                .layers
+                .iter()
+                .find(|layer| &layer.path == path)
+                .map(|layer| layer.location(key)),
+            ConfigSource::Env(name) => Some(name.clone()),
+            ConfigSource::Default => self
+                .layers
+                .iter()
+                .rev()
+                .find(|layer| layer.lines.line_of(key).is_some())
+                .map(|layer| layer.location(key)),
+        }
+    }
+
+    /// Layer the value at a dotted path such as `globals.external_id` came from
+    pub fn source(&self, key: &str) -> &ConfigSource {
+        self.sources.get(key).unwrap_or(&ConfigSource::Default)
+    }
+
+    /// Every set value of the merged config as `key = value  # source` lines
+    pub fn show(&self) -> String {
+        let mut leaves = BTreeMap::new();
+        let config = serde_json::to_value(&self.config).unwrap_or_default();
+        collect_leaves(&config, "", &mut leaves);
+        leaves
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| {
+                let value = toml::Value::try_from(value)
+                    .map(|value| value.to_string())
+                    .unwrap_or_default();
+                format!("{} = {}  # {}", key, value, self.source(&key))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 }
 
-pub fn load_test_config(config_path: &str) -> Result<TestConfig, String> {
-    let path = Path::new(config_path);
-    if !path.exists() {
-        return Err(format!("Configuration file not found: {}", config_path));
+/// Unknown keys and values of the wrong type, in the files and the environment
+fn schema_diagnostics(
+    layers: &[Layer],
+    merged: &toml::Table,
+    sources: &BTreeMap<String, ConfigSource>,
+) -> Vec<Diagnostic> {
+    let schema = serde_json::to_value(TestConfig::default()).unwrap_or_default();
+    let mut diagnostics = Vec::new();
+    for layer in layers {
+        for key in config_check::unknown_keys(&layer.table, &schema, "") {
+            let location = Some(layer.location(&key));
+            diagnostics
+                .push(Diagnostic::for_key(&key, format!("unknown key {}", key)).at(location));
+        }
+        for (key, value) in config_check::single_values(&layer.table, "") {
+            if let Some(message) = type_error(value) {
+                let location = Some(layer.location(&key));
+                diagnostics
+                    .push(Diagnostic::for_key(&key, format!("{}: {}", key, message)).at(location));
+            }
+        }
     }
+    for (key, value) in config_check::single_values(merged, "") {
+        if let Some(ConfigSource::Env(name)) = sources.get(&key)
+            && let Some(message) = type_error(value)
+        {
+            diagnostics.push(
+                Diagnostic::for_key(&key, format!("{}: {}", key, message)).at(Some(name.clone())),
+            );
+        }
+    }
+    diagnostics
+}
 
-    let contents = fs::read_to_string(path)
-        .map_err(|e| format!("Failed to read configuration file: {}", e))?;
+/// Why the value can't be read into `TestConfig`, if it can't
+fn type_error(value: toml::Table) -> Option<String> {
+    toml::Value::Table(value)
+        .try_into::<TestConfig>()
+        .err()
+        .map(|e| e.message().to_string())
+}
 
-    toml::from_str(&contents).map_err(|e| format!("Failed to parse configuration file: {}", e))
+/// The file an environment overlay is layered on: `tests.toml` for
+/// `tests.dev.toml`. Files without an environment part have no base
+fn base_config_path(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_str()?;
+    let (name, rest) = file_name.split_once('.')?;
+    if name.is_empty() || rest == "toml" || !rest.ends_with(".toml") {
+        return None;
+    }
+    Some(path.with_file_name(format!("{}.toml", name)))
 }
 
-pub fn validate_test_config(config: &TestConfig) -> Result<(), String> {
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+/// Merges `overlay` into `base`, recording `source` for every value it sets
+fn merge(
+    base: &mut toml::Table,
+    overlay: toml::Table,
+    prefix: &str,
+    source: &ConfigSource,
+    sources: &mut BTreeMap<String, ConfigSource>,
+) {
+    for (key, value) in overlay {
+        let path = join_key(prefix, &key);
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge(base, overlay, &path, source, sources);
+            }
+            (_, toml::Value::Table(overlay)) => {
+                sources.retain(|key, _| *key != path);
+                let mut table = toml::Table::new();
+                merge(&mut table, overlay, &path, source, sources);
+                base.insert(key, toml::Value::Table(table));
+            }
+            (_, value) => {
+                let nested = format!("{}.", path);
+                sources.retain(|key, _| !key.starts_with(&nested));
+                sources.insert(path, source.clone());
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Overrides values with `TIGERCLAW_*` variables. Every field of `TestConfig`
+/// and every key set in a file can be overridden, named by its dotted path in
+/// upper case with underscores, e.g. `TIGERCLAW_SAS_DATA_IMPORT_ENABLED=true`
+fn apply_env(
+    merged: &mut toml::Table,
+    vars: impl IntoIterator<Item = (String, String)>,
+    sources: &mut BTreeMap<String, ConfigSource>,
+) -> Result<(), TigerClawError> {
+    let mut vars: BTreeMap<String, String> = vars
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+    if vars.is_empty() {
+        return Ok(());
+    }
+
+    let mut fields = BTreeMap::new();
+    let defaults = serde_json::to_value(TestConfig::default()).unwrap_or_default();
+    collect_leaves(&defaults, "", &mut fields);
+    let files = serde_json::to_value(&*merged).unwrap_or_default();
+    collect_leaves(&files, "", &mut fields);
+
+    for (key, default) in fields {
+        let name = format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase());
+        let Some(raw) = vars.remove(&name) else {
+            continue;
+        };
+        let value = if default.is_string() {
+            toml::Value::String(raw)
+        } else {
+            parse_env_value(&raw)
+        };
+        let (parents, field) = key.rsplit_once('.').unwrap_or(("", &key));
+        let mut table = &mut *merged;
+        for parent in parents.split('.').filter(|parent| !parent.is_empty()) {
+            let entry = table
+                .entry(parent)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            table = entry.as_table_mut().ok_or_else(|| {
+                TigerClawError::Config(format!("{} is not a table, can't apply {}", parent, name))
+            })?;
+        }
+        table.insert(field.to_string(), value);
+        sources.insert(key.clone(), ConfigSource::Env(name));
+    }
+
+    for name in vars.keys() {
+        warn!(
+            "{} does not match any configuration field, ignoring it",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Reads a variable as a TOML value so numbers, booleans and arrays keep their
+/// type, anything else is taken as a string
+fn parse_env_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+fn collect_leaves(
+    value: &serde_json::Value,
+    prefix: &str,
+    leaves: &mut BTreeMap<String, serde_json::Value>,
+) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, value) in fields {
+                collect_leaves(value, &join_key(prefix, key), leaves);
+            }
+        }
+        value => {
+            leaves.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+/// Checks every value of the config and reports all the problems at once
+pub fn validate_test_config(config: &TestConfig) -> Result<(), TigerClawError> {
+    into_result(&problems(config))
+}
+
+fn into_result(diagnostics: &[Diagnostic]) -> Result<(), TigerClawError> {
+    if diagnostics.is_empty() {
+        return Ok(());
+    }
+    Err(TigerClawError::Config(format!(
+        "Configuration validation failed, {}",
+        config_check::report(diagnostics)
+    )))
+}
+
+/// Every problem with the values of the config, keyed by the value's dotted
+/// path where there is one
+fn problems(config: &TestConfig) -> Vec<Diagnostic> {
+    let mut problems = Vec::new();
     if !config.orchestration.enabled
         && !config.sas_data_import.enabled
         && !config.dynamo_db.enabled
         && !config.migration_api.enabled
+        && !config.terms.enabled
     {
-        return Err("At least one test type must be enabled in the configuration".to_string());
+        problems.push(Diagnostic::new(
+            "At least one test type must be enabled in the configuration",
+        ));
     }
 
-    // Validate global config
-    config.globals.validate()?;
+    problems.extend(config.globals.problems());
+    if let Err(error) = config.timeouts.validate() {
+        problems.push(Diagnostic::for_key("timeouts", error));
+    }
 
-    Ok(())
+    let orchestration = &config.orchestration;
+    if orchestration.step_to_run.trim().is_empty() {
+        if orchestration.enabled {
+            problems.push(Diagnostic::for_key(
+                "orchestration.step_to_run",
+                "orchestration.step_to_run is required when orchestration is enabled, e.g. \"VALID\" or \"VALID..FEE\"",
+            ));
+        }
+    } else if let Err(error) = StepDescriptor::parse_list(&orchestration.step_to_run) {
+        problems.push(Diagnostic::for_key("orchestration.step_to_run", error));
+    }
+    if orchestration.step_status_to_force.is_empty() {
+        if orchestration.force_run {
+            problems.push(Diagnostic::for_key(
+                "orchestration.force_run",
+                "orchestration.step_status_to_force is required when force_run is set",
+            ));
+        }
+    } else if MigrationStatus::from_string(
+        &orchestration.step_status_to_force,
+        StepDescriptor::Init,
+    )
+    .is_none()
+    {
+        problems.push(Diagnostic::for_key(
+            "orchestration.step_status_to_force",
+            format!(
+                "Invalid step_status_to_force: {}. Must be a migration status such as INIT_DONE",
+                orchestration.step_status_to_force
+            ),
+        ));
+    }
+
+    // Only the services an enabled suite talks to need a base URL
+    let globals = &config.globals;
+    if (orchestration.enabled || config.migration_api.enabled || config.terms.enabled)
+        && is_unset(&globals.growth_migration_url)
+        && is_unset(&globals.base_growth_migration_url)
+    {
+        problems.push(Diagnostic::for_key(
+            "globals.growth_migration_url",
+            "globals.growth_migration_url is required when orchestration, migration_api or terms is enabled",
+        ));
+    }
+    if (config.sas_data_import.enabled || config.migration_api.enabled)
+        && is_unset(&globals.sas_data_import_url)
+        && is_unset(&globals.base_sas_data_import_url)
+    {
+        problems.push(Diagnostic::for_key(
+            "globals.sas_data_import_url",
+            "globals.sas_data_import_url is required when sas_data_import or migration_api is enabled, migration_api reads the merchant back to verify its locks",
+        ));
+    }
+
+    for (name, path) in &config.sas_data_import.endpoints {
+        let key = format!("sas_data_import.endpoints.{}", name);
+        if SasResource::from_string(name).is_none() {
+            problems.push(Diagnostic::for_key(
+                &key,
+                format!(
+                    "Unknown SAS resource '{}', expected one of merchant, affiliates, program, commissions, vouchers, feeds or creatives",
+                    name
+                ),
+            ));
+        } else if !path.contains("{merchant_id}") {
+            problems.push(Diagnostic::for_key(
+                &key,
+                format!("Endpoint '{}' for {} has no {{merchant_id}}", path, name),
+            ));
+        }
+    }
+
+    if let Some(url) = config.auth.idp_url.as_deref()
+        && let Err(error) = base_url::resolve("idp_url", Some(url), None, None)
+    {
+        problems.push(Diagnostic::for_key("auth.idp_url", error));
+    }
+    let allowlists = [
+        (
+            "allowed_external_ids",
+            &config.guardrails.allowed_external_ids,
+        ),
+        (
+            "allowed_advertiser_ids",
+            &config.guardrails.allowed_advertiser_ids,
+        ),
+    ];
+    for (field, allowlist) in allowlists {
+        for environment in allowlist.keys() {
+            if Environment::from_name(environment).is_none() {
+                problems.push(Diagnostic::for_key(
+                    &format!("guardrails.{}.{}", field, environment),
+                    format!(
+                        "Invalid environment in guardrails.{}: {}. Must be local, dev, staging or production",
+                        field, environment
+                    ),
+                ));
+            }
+        }
+    }
+
+    if let Err(suite_problems) = SuitePlan::new(&config.suites) {
+        problems.extend(suite_problems);
+    }
+
+    if config.auth.client_secret.is_some() && config.auth.client_secret_env.is_some() {
+        problems.push(Diagnostic::for_key(
+            "auth.client_secret",
+            "Set only one of auth.client_secret and auth.client_secret_env",
+        ));
+    }
+    for (service, auth) in &config.auth.services {
+        if let Err(error) = auth.secret() {
+            problems.push(Diagnostic::for_key(
+                &format!("auth.services.{}", service),
+                format!("{} for service {}", error, service),
+            ));
+        }
+    }
+
+    problems
+}
+
+fn is_unset(value: &Option<String>) -> bool {
+    value.as_deref().is_none_or(|value| value.trim().is_empty())
 }
 
 #[cfg(test)]
@@ -133,10 +926,13 @@ mod tests {
             external_id: Some(123),
             migration_name: Some("sas".to_string()),
             environment: "dev".to_string(),
-            base_growth_migration_url: "http://example.com".to_string(),
-            base_growth_migration_port: 8080,
-            base_sas_data_import_url: "http://example.com".to_string(),
-            base_sas_data_import_port: 8181,
+            growth_migration_url: None,
+            sas_data_import_url: None,
+            base_growth_migration_url: Some("http://example.com".to_string()),
+            base_growth_migration_port: Some(8080),
+            base_sas_data_import_url: Some("http://example.com".to_string()),
+            base_sas_data_import_port: Some(8181),
+            read_only: false,
         };
         assert!(valid_config.validate().is_ok());
 
@@ -146,10 +942,13 @@ mod tests {
             external_id: Some(123),
             migration_name: Some("SAS".to_string()),
             environment: "DEV".to_string(),
-            base_growth_migration_url: "http://example.com".to_string(),
-            base_growth_migration_port: 8080,
-            base_sas_data_import_url: "http://example.com".to_string(),
-            base_sas_data_import_port: 8181,
+            growth_migration_url: None,
+            sas_data_import_url: None,
+            base_growth_migration_url: Some("http://example.com".to_string()),
+            base_growth_migration_port: Some(8080),
+            base_sas_data_import_url: Some("http://example.com".to_string()),
+            base_sas_data_import_port: Some(8181),
+            read_only: false,
         };
         assert!(uppercase_config.validate().is_ok());
 
@@ -159,10 +958,13 @@ mod tests {
             external_id: Some(123),
             migration_name: Some("sas".to_string()),
             environment: "dev".to_string(),
-            base_growth_migration_url: "http://example.com".to_string(),
-            base_growth_migration_port: 8080,
-            base_sas_data_import_url: "http://example.com".to_string(),
-            base_sas_data_import_port: 8181,
+            growth_migration_url: None,
+            sas_data_import_url: None,
+            base_growth_migration_url: Some("http://example.com".to_string()),
+            base_growth_migration_port: Some(8080),
+            base_sas_data_import_url: Some("http://example.com".to_string()),
+            base_sas_data_import_port: Some(8181),
+            read_only: false,
         };
         assert!(invalid_advertiser_id.validate().is_err());
 
@@ -172,10 +974,13 @@ mod tests {
             external_id: Some(-1),
             migration_name: Some("sas".to_string()),
             environment: "dev".to_string(),
-            base_growth_migration_url: "http://example.com".to_string(),
-            base_growth_migration_port: 8080,
-            base_sas_data_import_url: "http://example.com".to_string(),
-            base_sas_data_import_port: 8181,
+            growth_migration_url: None,
+            sas_data_import_url: None,
+            base_growth_migration_url: Some("http://example.com".to_string()),
+            base_growth_migration_port: Some(8080),
+            base_sas_data_import_url: Some("http://example.com".to_string()),
+            base_sas_data_import_port: Some(8181),
+            read_only: false,
         };
         assert!(invalid_external_id.validate().is_err());
 
@@ -185,10 +990,13 @@ mod tests {
             external_id: Some(123),
             migration_name: Some("not_sas".to_string()),
             environment: "dev".to_string(),
-            base_growth_migration_url: "http://example.com".to_string(),
-            base_growth_migration_port: 8080,
-            base_sas_data_import_url: "http://example.com".to_string(),
-            base_sas_data_import_port: 8181,
+            growth_migration_url: None,
+            sas_data_import_url: None,
+            base_growth_migration_url: Some("http://example.com".to_string()),
+            base_growth_migration_port: Some(8080),
+            base_sas_data_import_url: Some("http://example.com".to_string()),
+            base_sas_data_import_port: Some(8181),
+            read_only: false,
         };
         assert!(invalid_migration_name.validate().is_err());
     }
@@ -222,19 +1030,32 @@ mod tests {
                 force_run: false,
                 step_status_to_force: String::new(),
             },
-            sas_data_import: SasDataImportConfig { enabled: false },
+            sas_data_import: SasDataImportConfig {
+                enabled: false,
+                resources: Vec::new(),
+                endpoints: HashMap::new(),
+            },
             dynamo_db: DynamoDbConfig { enabled: false },
             globals: GlobalConfig {
                 advertiser_id: None,
                 external_id: None,
                 migration_name: None,
                 environment: "dev".to_string(),
-                base_growth_migration_url: "http://example.com".to_string(),
-                base_growth_migration_port: 8080,
-                base_sas_data_import_url: "http://example.com".to_string(),
-                base_sas_data_import_port: 8181,
+                growth_migration_url: None,
+                sas_data_import_url: None,
+                base_growth_migration_url: Some("http://example.com".to_string()),
+                base_growth_migration_port: Some(8080),
+                base_sas_data_import_url: Some("http://example.com".to_string()),
+                base_sas_data_import_port: Some(8181),
+                read_only: false,
             },
-            migration_api: MigrationApiConfig { enabled: false },
+            migration_api: MigrationApiConfig::default(),
+            terms: TermsConfig::default(),
+            auth: AuthConfig::default(),
+            retry: RetryConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            guardrails: GuardrailConfig::default(),
+            suites: SuiteConfig::default(),
         };
         assert!(validate_test_config(&config).is_err());
     }
@@ -244,25 +1065,53 @@ mod tests {
         let config = TestConfig {
             orchestration: OrchestrationConfig {
                 enabled: true,
-                step_to_run: String::new(),
+                step_to_run: "VALID".to_string(),
                 force_run: false,
                 step_status_to_force: String::new(),
             },
-            sas_data_import: SasDataImportConfig { enabled: false },
+            sas_data_import: SasDataImportConfig {
+                enabled: false,
+                resources: Vec::new(),
+                endpoints: HashMap::new(),
+            },
             dynamo_db: DynamoDbConfig { enabled: false },
             globals: GlobalConfig {
                 advertiser_id: None,
                 external_id: None,
                 migration_name: None,
                 environment: "dev".to_string(),
-                base_growth_migration_url: "http://example.com".to_string(),
-                base_growth_migration_port: 8080,
-                base_sas_data_import_url: "http://example.com".to_string(),
-                base_sas_data_import_port: 8181,
+                growth_migration_url: None,
+                sas_data_import_url: None,
+                base_growth_migration_url: Some("http://example.com".to_string()),
+                base_growth_migration_port: Some(8080),
+                base_sas_data_import_url: Some("http://example.com".to_string()),
+                base_sas_data_import_port: Some(8181),
+                read_only: false,
             },
-            migration_api: MigrationApiConfig { enabled: false },
+            migration_api: MigrationApiConfig::default(),
+            terms: TermsConfig::default(),
+            auth: AuthConfig::default(),
+            retry: RetryConfig::default(),
+            timeouts: TimeoutConfig::default(),
+            guardrails: GuardrailConfig::default(),
+            suites: SuiteConfig::default(),
         };
         assert!(validate_test_config(&config).is_ok());
+
+        // Orchestration without steps would do nothing
+        let config = TestConfig {
+            orchestration: OrchestrationConfig {
+                step_to_run: String::new(),
+                ..config.orchestration
+            },
+            ..config
+        };
+        let error = validate_test_config(&config).unwrap_err().to_string();
+        assert!(
+            error.contains("orchestration.step_to_run is required when orchestration is enabled"),
+            "{}",
+            error
+        );
     }
 
     #[test]
@@ -272,4 +1121,131 @@ mod tests {
         assert!(config.sas_data_import.enabled);
         assert!(config.dynamo_db.enabled);
     }
+
+    #[test]
+    fn overlay_and_env_are_layered_on_the_base_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("tests.toml");
+        let overlay = dir.path().join("tests.dev.toml");
+        fs::write(
+            &base,
+            "[orchestration]\nstep_status_to_force = \"INIT_DONE\"\n[globals]\nadvertiser_id = 1\nexternal_id = 1\n",
+        )
+        .unwrap();
+        fs::write(
+            &overlay,
+            "[orchestration]\nenabled = true\n[globals]\nexternal_id = 2\nenvironment = \"dev\"\n",
+        )
+        .unwrap();
+        let vars = [
+            ("TIGERCLAW_GLOBALS_ADVERTISER_ID", "3"),
+            ("TIGERCLAW_GLOBALS_MIGRATION_NAME", "sas"),
+            ("TIGERCLAW_RETRY_RETRYABLE_STATUSES", "[500]"),
+            ("PATH", "/usr/bin"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+
+        let layered = LayeredConfig::load_with_env(overlay.to_str().unwrap(), vars).unwrap();
+        let config = &layered.config;
+        assert!(config.orchestration.enabled);
+        assert_eq!(config.orchestration.step_status_to_force, "INIT_DONE");
+        assert_eq!(config.globals.advertiser_id, Some(3));
+        assert_eq!(config.globals.external_id, Some(2));
+        assert_eq!(config.globals.migration_name.as_deref(), Some("sas"));
+        assert_eq!(config.retry.retryable_statuses, vec![500]);
+
+        assert_eq!(
+            layered.source("orchestration.step_status_to_force"),
+            &ConfigSource::File(base)
+        );
+        assert_eq!(
+            layered.source("globals.external_id"),
+            &ConfigSource::File(overlay.clone())
+        );
+        assert_eq!(
+            layered.source("globals.advertiser_id"),
+            &ConfigSource::Env("TIGERCLAW_GLOBALS_ADVERTISER_ID".to_string())
+        );
+        assert_eq!(layered.source("dynamo_db.enabled"), &ConfigSource::Default);
+
+        let shown = layered.show();
+        assert!(shown.contains(&format!("globals.external_id = 2  # {}", overlay.display())));
+        assert!(shown.contains("retry.max_attempts = 3  # default"));
+    }
+
+    #[test]
+    fn only_environment_overlays_have_a_base_file() {
+        assert_eq!(
+            base_config_path(Path::new("config/tests.dev.toml")),
+            Some(PathBuf::from("config/tests.toml"))
+        );
+        assert_eq!(base_config_path(Path::new("tests.toml")), None);
+        assert_eq!(base_config_path(Path::new("/tmp/.tmpA1b2")), None);
+        assert_eq!(base_config_path(Path::new("custom")), None);
+    }
+
+    #[test]
+    fn unknown_keys_and_mistyped_values_fail_loading_with_their_lines() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "[orchestration]\nenabled = \"yes\"\n\n[globals]\nforce = false\nexternal_id = 1\n"
+        )
+        .unwrap();
+        let path = file.path().to_str().unwrap();
+        let vars = [("TIGERCLAW_RETRY_MAX_ATTEMPTS", "many")]
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+
+        let error = LayeredConfig::load_with_env(path, vars)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("3 problems found"), "{}", error);
+        assert!(error.contains(&format!("{}:5: unknown key globals.force", path)));
+        assert!(error.contains(&format!("{}:2: orchestration.enabled: invalid type", path)));
+        assert!(error.contains("TIGERCLAW_RETRY_MAX_ATTEMPTS: retry.max_attempts"));
+    }
+
+    #[test]
+    fn every_invalid_value_is_reported_at_once() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "[orchestration]\nenabled = true\nstep_to_run = \"VALID..NOPE\"\nforce_run = true\n\n[globals]\nexternal_id = 0\nbase_growth_migration_url = \"localhost\"\nbase_growth_migration_port = 70000\n"
+        )
+        .unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let layered = LayeredConfig::load_with_env(path, []).unwrap();
+        let error = layered.validate().unwrap_err().to_string();
+        assert!(error.contains("5 problems found"), "{}", error);
+        for expected in [
+            format!("{}:3: Unknown step NOPE", path),
+            format!("{}:4: orchestration.step_status_to_force is required", path),
+            format!("{}:7: Invalid external_id: 0", path),
+            format!("{}:8: Invalid base URL for base_growth_migration_url", path),
+            format!("{}:9: Invalid base_growth_migration_port: 70000", path),
+        ] {
+            assert!(error.contains(&expected), "{} not in {}", expected, error);
+        }
+    }
+
+    #[test]
+    fn sas_endpoints_must_name_a_resource_and_the_merchant() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "[sas_data_import]\nenabled = true\n\n[sas_data_import.endpoints]\nvouchers = \"codes/{{merchant_id}}\"\nwidgets = \"widgets/{{merchant_id}}\"\nfeeds = \"feeds\"\n\n[globals]\nexternal_id = 1\nbase_sas_data_import_url = \"http://localhost\"\n"
+        )
+        .unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let layered = LayeredConfig::load_with_env(path, []).unwrap();
+        let error = layered.validate().unwrap_err().to_string();
+        assert!(error.contains("2 problems found"), "{}", error);
+        assert!(error.contains(&format!("{}:6: Unknown SAS resource 'widgets'", path)));
+        assert!(error.contains(&format!(
+            "{}:7: Endpoint 'feeds' for feeds has no {{merchant_id}}",
+            path
+        )));
+    }
 }
@@ -0,0 +1,233 @@
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt;
+
+/// One problem found in the configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Dotted path of the value, e.g. `globals.external_id`
+    pub key: Option<String>,
+    /// Where the value was set, `file:line` or the environment variable
+    pub location: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Diagnostic {
+            key: None,
+            location: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn for_key(key: &str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            key: Some(key.to_string()),
+            ..Diagnostic::new(message)
+        }
+    }
+
+    pub fn at(mut self, location: Option<String>) -> Self {
+        self.location = location;
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{}: {}", location, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// All the problems as one message, one per line
+pub fn report(diagnostics: &[Diagnostic]) -> String {
+    let mut lines = vec![format!(
+        "{} problem{} found",
+        diagnostics.len(),
+        if diagnostics.len() == 1 { "" } else { "s" }
+    )];
+    lines.extend(diagnostics.iter().map(|d| format!("  {}", d)));
+    lines.join("\n")
+}
+
+/// Line of every table header and key in a TOML file, by dotted path. Only
+/// plain `[table]` headers and `key = value` lines are indexed, which covers
+/// how our config files are written
+#[derive(Debug, Clone, Default)]
+pub struct KeyLines {
+    lines: HashMap<String, usize>,
+}
+
+impl KeyLines {
+    pub fn parse(contents: &str) -> Self {
+        let mut lines = HashMap::new();
+        let mut table = String::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if let Some(header) = line
+                .strip_prefix('[')
+                .and_then(|rest| rest.split(']').next())
+                .filter(|_| !line.starts_with("[["))
+            {
+                table = unquote_path(header);
+                lines.insert(table.clone(), index + 1);
+            } else if let Some((key, _)) = line.split_once('=')
+                && !line.starts_with('#')
+            {
+                let key = unquote_path(key);
+                if !key.is_empty() {
+                    let path = if table.is_empty() {
+                        key
+                    } else {
+                        format!("{}.{}", table, key)
+                    };
+                    lines.entry(path).or_insert(index + 1);
+                }
+            }
+        }
+        KeyLines { lines }
+    }
+
+    /// Line of the key, or of the closest table it is nested in
+    pub fn line_of(&self, key: &str) -> Option<usize> {
+        let mut key = key;
+        loop {
+            if let Some(line) = self.lines.get(key) {
+                return Some(*line);
+            }
+            key = key.rsplit_once('.')?.0;
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.lines.contains_key(key)
+    }
+}
+
+fn unquote_path(path: &str) -> String {
+    path.split('.')
+        .map(|part| part.trim().trim_matches('"'))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Tables whose keys are names picked by the user, mapped to the keys each
+/// entry may have
+fn map_entry_schema(path: &str) -> Option<Value> {
+    match path {
+        "auth.services" => Some(json!({
+            "type": null, "header": null, "env": null, "file": null, "secret": null, "scheme": null,
+        })),
+        "timeouts.services" => Some(json!({ "connect_seconds": null, "read_seconds": null })),
+        "sas_data_import.endpoints" => Some(json!("")),
+        "guardrails.allowed_external_ids"
+        | "guardrails.allowed_advertiser_ids"
+        | "suites.depends_on" => Some(json!([])),
+        _ => None,
+    }
+}
+
+/// Whether the entries of the table at `path` have to be deserialized whole,
+/// because which keys they need depends on each other
+pub fn is_whole_entry(path: &str) -> bool {
+    path.rsplit_once('.')
+        .is_some_and(|(parent, _)| parent == "auth.services")
+}
+
+/// Dotted paths of keys in `table` that aren't in `schema`, the serialized
+/// defaults of the config
+pub fn unknown_keys(table: &toml::Table, schema: &Value, prefix: &str) -> Vec<String> {
+    let mut unknown = Vec::new();
+    for (key, value) in table {
+        let path = join(prefix, key);
+        let entry_schema = map_entry_schema(prefix);
+        let child = match &entry_schema {
+            Some(entry) => Some(entry),
+            None => schema.get(key),
+        };
+        match (child, value) {
+            (None, _) => unknown.push(path),
+            (Some(child), toml::Value::Table(table)) if child.is_object() => {
+                unknown.extend(unknown_keys(table, child, &path));
+            }
+            _ => {}
+        }
+    }
+    unknown
+}
+
+/// Every value in `table` as a table holding only that value, keyed by its
+/// dotted path, so each one can be type checked on its own
+pub fn single_values(table: &toml::Table, prefix: &str) -> Vec<(String, toml::Table)> {
+    let mut values = Vec::new();
+    for (key, value) in table {
+        let path = join(prefix, key);
+        match value {
+            toml::Value::Table(nested) if !is_whole_entry(&path) => {
+                for (nested_path, nested_table) in single_values(nested, &path) {
+                    values.push((nested_path, wrap(key, nested_table)));
+                }
+            }
+            value => {
+                let mut single = toml::Table::new();
+                single.insert(key.clone(), value.clone());
+                values.push((path, single));
+            }
+        }
+    }
+    values
+}
+
+fn wrap(key: &str, table: toml::Table) -> toml::Table {
+    let mut wrapped = toml::Table::new();
+    wrapped.insert(key.to_string(), toml::Value::Table(table));
+    wrapped
+}
+
+fn join(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_found_for_keys_and_tables() {
+        let lines = KeyLines::parse(
+            "# comment\n[orchestration]\nenabled = true\nstep_to_run=\"VALID\"\n\n[auth.services.\"sas_data_import\"]\ntype = \"api_key\"\n",
+        );
+        assert_eq!(lines.line_of("orchestration"), Some(2));
+        assert_eq!(lines.line_of("orchestration.step_to_run"), Some(4));
+        assert_eq!(lines.line_of("auth.services.sas_data_import.type"), Some(7));
+        // Keys that aren't in the file point at their table
+        assert_eq!(lines.line_of("orchestration.force_run"), Some(2));
+        assert_eq!(lines.line_of("globals.external_id"), None);
+    }
+
+    #[test]
+    fn unknown_keys_are_found_in_sections_and_map_entries() {
+        let schema = json!({
+            "globals": { "external_id": null, "read_only": false },
+            "auth": { "services": {} },
+        });
+        let table: toml::Table = toml::from_str(
+            "[globals]\nexternal_id = 1\nforce = false\n[auth.services.sas]\ntype = \"api_key\"\nenv = \"X\"\nheadr = \"Y\"\n[nope]\n",
+        )
+        .unwrap();
+        let mut unknown = unknown_keys(&table, &schema, "");
+        unknown.sort();
+        assert_eq!(
+            unknown,
+            vec!["auth.services.sas.headr", "globals.force", "nope"]
+        );
+    }
+}
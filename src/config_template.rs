@@ -0,0 +1,260 @@
+use crate::error::TigerClawError;
+use crate::setup::{Environment, IdpSettings};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Service URLs known for an environment, empty where there is none to suggest
+struct KnownUrls {
+    growth_migration: &'static str,
+    sas_data_import: &'static str,
+    /// Note above the URLs, e.g. which tunnel they go through
+    note: &'static str,
+}
+
+fn known_urls(environment: Environment) -> KnownUrls {
+    match environment {
+        Environment::Local => KnownUrls {
+            growth_migration: "http://localhost:8080",
+            sas_data_import: "http://localhost:8181",
+            note: "Services running on this machine",
+        },
+        Environment::Dev => KnownUrls {
+            growth_migration: "https://growth-account-migration-service.springfield-dev.aws.awin.com",
+            sas_data_import: "http://localhost:8181",
+            note: "SAS data import is only reachable through the tunnel opened by\n# `make sas-data-import-ssm-dev`",
+        },
+        Environment::Staging => KnownUrls {
+            growth_migration: "http://growth-account-migration-service.cloudmap.eu-west-1.springfield-staging.awin-aws.com",
+            sas_data_import: "http://sas-data-import.cloudmap.eu-west-1.springfield-staging.awin-aws.com",
+            note: "Cloud Map addresses, reachable from inside the staging VPC",
+        },
+        Environment::Production => KnownUrls {
+            growth_migration: "",
+            sas_data_import: "",
+            note: "There are no known production URLs, fill them in before running",
+        },
+    }
+}
+
+/// Where `config init` writes the template when no path is given. It isn't a
+/// `tests.*.toml` name so it is never layered on `tests.toml`
+pub fn default_path(environment: Environment) -> PathBuf {
+    PathBuf::from(format!("tigerclaw.{}.toml", environment.name()))
+}
+
+/// A commented config covering every section, with the service URLs known for
+/// the environment. IDs are left out so nobody inherits someone else's test
+/// advertiser
+pub fn render(environment: Environment) -> String {
+    let urls = known_urls(environment);
+    let url_line = |key: &str, url: &str| {
+        if url.is_empty() {
+            format!("# {} = \"\"", key)
+        } else {
+            format!("{} = \"{}\"", key, url)
+        }
+    };
+    let (read_only, auth) = match environment {
+        Environment::Local | Environment::Dev => (
+            "false",
+            "# The dev IdP and AWIN_SPRINGFIELD_DEV_CLIENT_SECRET are used by default",
+        ),
+        Environment::Staging => (
+            "false",
+            "# Required for staging, there is no built in default\n# idp_url = \"\"",
+        ),
+        Environment::Production => (
+            "true",
+            "# Required for production, there is no built in default\n# idp_url = \"\"",
+        ),
+    };
+
+    format!(
+        r#"# TigerClaw {environment} configuration, generated by `tiger-claw config init`.
+#
+# Fill in the IDs of the advertiser you are testing with, then check the file
+# with `tiger-claw -n {environment} -c <this file> config check`. Values left out
+# take their defaults, TIGERCLAW_* environment variables override anything set
+# here, e.g. TIGERCLAW_GLOBALS_EXTERNAL_ID=44911
+
+[orchestration]
+# Runs migration steps against growth-account-migration-service
+enabled = true
+# Steps to run, a list or an inclusive range, e.g. "VALID", "SF,ADV" or "VALID..FEE"
+step_to_run = "VALID"
+# Force the advertiser into step_status_to_force before running the steps
+force_run = false
+step_status_to_force = "INIT_DONE"
+
+[sas_data_import]
+# Pulls the merchant's source data from the SAS data import service
+enabled = false
+# Defaults to the merchant and the source data of orchestration.step_to_run
+# resources = ["merchant", "affiliates", "program", "commissions", "vouchers", "feeds", "creatives"]
+
+# Paths the resources are read from, only merchant/{{merchant_id}} is a known
+# route, replace the others with the service's once they are published
+# [sas_data_import.endpoints]
+# vouchers = "merchant/{{merchant_id}}/vouchers"
+
+[dynamo_db]
+# Reads the advertiser record of external_id from DynamoDB and checks it
+enabled = false
+
+[terms]
+# Fetches and validates the terms and conditions of advertiser_id
+enabled = false
+
+[migration_api]
+# Locks the merchant down and locks its fees, then reads the merchant back from
+# SAS data import to check both took
+enabled = false
+# Lift both locks again afterwards, so the test merchant is back to normal
+unlock_after_run = false
+
+[globals]
+environment = "{environment}"
+# SAS external ID and Awin advertiser ID of the advertiser to test with
+# external_id =
+# advertiser_id =
+migration_name = "sas"
+# Refuse every mutating call
+read_only = {read_only}
+# {note}
+{growth_migration_url}
+{sas_data_import_url}
+
+[auth]
+{auth}
+# client_id = "migrationTestClient"
+# Secrets are references, env:NAME, file:/path or aws-ssm:/parameter/name
+# client_secret = "{client_secret}"
+# Reuse the token between runs
+cache_token_file = false
+refresh_margin_seconds = 60
+# How a service authenticates when it doesn't use the IdP token, e.g.
+# [auth.services.sas_data_import]
+# type = "api_key"
+# header = "Authorization"
+# secret = "env:AWIN_SAS_DATA_IMPORT_API_SECRET"
+
+[guardrails]
+# The only advertisers execute-step, force status, the locks and deletes may
+# change in {environment}. Outside local and dev they also need --allow-mutations
+# allowed_external_ids.{environment} = []
+# allowed_advertiser_ids.{environment} = []
+
+[suites]
+# Order the enabled suites run in, a suite always runs after its dependencies
+order = ["orchestration", "sas_data_import", "terms", "dynamo_db", "migration_api"]
+# Suites that have to pass first, otherwise the suite is skipped, e.g.
+# depends_on.orchestration = ["sas_data_import"]
+# depends_on.migration_api = ["orchestration"]
+# Suites that don't depend on each other run at the same time, up to this many.
+# Their output is written per suite once each finishes
+concurrency = 1
+# Check growth-account-migration-service, SAS data import and DynamoDB are up
+# first, suites needing one that isn't are skipped as service unavailable
+health_checks = true
+
+[retry]
+# Transient gateway errors and dropped connections are retried with backoff.
+# execute-step and the merchant locks are only retried with retry_non_idempotent
+max_attempts = 3
+initial_backoff_ms = 200
+max_backoff_ms = 5000
+retryable_statuses = [502, 503, 504]
+retry_non_idempotent = false
+
+[timeouts]
+# A hung tunnel fails the check instead of blocking the run
+connect_seconds = 10
+read_seconds = 60
+# run_deadline_seconds = 900
+# [timeouts.services.sas_data_import]
+# read_seconds = 180
+"#,
+        environment = environment.name(),
+        read_only = read_only,
+        note = urls.note,
+        growth_migration_url = url_line("growth_migration_url", urls.growth_migration),
+        sas_data_import_url = url_line("sas_data_import_url", urls.sas_data_import),
+        auth = auth,
+        client_secret = IdpSettings::default_client_secret(environment),
+    )
+}
+
+/// Writes the template for the environment to `path`, or `default_path`.
+/// An existing file is only replaced when `force` is set
+pub fn write(
+    environment: Environment,
+    path: Option<&Path>,
+    force: bool,
+) -> Result<PathBuf, TigerClawError> {
+    let path = path
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| default_path(environment));
+    if path.exists() && !force {
+        return Err(TigerClawError::Config(format!(
+            "{} already exists, pass --force to replace it",
+            path.display()
+        )));
+    }
+    fs::write(&path, render(environment)).map_err(|e| {
+        TigerClawError::Config(format!("Failed to write {}: {}", path.display(), e))
+    })?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LayeredConfig;
+
+    #[test]
+    fn templates_load_and_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        for environment in [Environment::Local, Environment::Dev, Environment::Staging] {
+            let path = write(
+                environment,
+                Some(&dir.path().join(default_path(environment))),
+                false,
+            )
+            .unwrap();
+            let layered = LayeredConfig::load(path.to_str().unwrap()).unwrap();
+            layered.validate().unwrap();
+            assert_eq!(layered.config.globals.environment, environment.name());
+            assert_eq!(layered.config.globals.external_id, None);
+        }
+
+        // Production has no URLs to suggest, config check says which are missing
+        let path = write(
+            Environment::Production,
+            Some(&dir.path().join("prod.toml")),
+            false,
+        )
+        .unwrap();
+        let layered = LayeredConfig::load(path.to_str().unwrap()).unwrap();
+        assert!(layered.config.globals.read_only);
+        let error = layered.validate().unwrap_err().to_string();
+        assert!(
+            error.contains("growth_migration_url is required"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn existing_files_are_only_replaced_with_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tests.local.toml");
+        fs::write(&path, "[globals]\nexternal_id = 1234\n").unwrap();
+
+        let error = write(Environment::Dev, Some(&path), false).unwrap_err();
+        assert!(error.to_string().contains("--force"), "{}", error);
+        assert!(fs::read_to_string(&path).unwrap().contains("1234"));
+
+        write(Environment::Dev, Some(&path), true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), render(Environment::Dev));
+    }
+}
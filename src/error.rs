@@ -0,0 +1,213 @@
+use crate::request::BufferedResponse;
+use aws_sdk_dynamodb::error::SdkError;
+use reqwest::StatusCode;
+use std::fmt;
+
+/// Longest response body kept in an error, error pages can be large
+const MAX_BODY_LENGTH: usize = 2000;
+
+/// Every way a TigerClaw run can fail, so callers can match on the kind of
+/// failure and the CLI can turn it into an exit code
+#[derive(Debug)]
+pub enum TigerClawError {
+    /// Missing or invalid configuration or arguments
+    Config(String),
+    /// Credentials couldn't be obtained, or the service rejected them
+    Auth(String),
+    /// The service answered with a status the call doesn't accept
+    Http {
+        url: String,
+        status: StatusCode,
+        body: String,
+    },
+    /// The request failed before a response arrived
+    Request(reqwest::Error),
+    /// No response within the configured timeouts
+    TimedOut(String),
+    /// The response didn't have the expected shape
+    Decode {
+        url: String,
+        message: String,
+    },
+    DynamoDb(Box<aws_sdk_dynamodb::Error>),
+    /// A mutating call was refused because the run is read only
+    ReadOnly(String),
+    /// A check ran but the data it looked at was wrong
+    Assertion(String),
+}
+
+impl TigerClawError {
+    /// Builds the error for a response the caller didn't accept, keeping the
+    /// body since that's usually where the service explains what went wrong
+    pub fn from_response(response: &BufferedResponse) -> Self {
+        let url = response.url().to_string();
+        let status = response.status();
+        let mut body = response.body().to_string();
+        if body.len() > MAX_BODY_LENGTH {
+            let mut end = MAX_BODY_LENGTH;
+            while !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            body.truncate(end);
+            body.push_str("...");
+        }
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => TigerClawError::Auth(format!(
+                "{} rejected the credentials with {}: {}",
+                url, status, body
+            )),
+            status => TigerClawError::Http { url, status, body },
+        }
+    }
+
+    /// Status of the response, for errors that had one
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            TigerClawError::Http { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Timed out requests are reported apart from failures, the service may
+    /// still have done the work
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, TigerClawError::TimedOut(_))
+    }
+
+    /// Process exit code for the CLI:
+    ///
+    /// | code | failure                           |
+    /// |------|-----------------------------------|
+    /// | 1    | assertion, the checks found a bug |
+    /// | 2    | config or read-only refusal       |
+    /// | 3    | auth                              |
+    /// | 4    | HTTP, request or decode           |
+    /// | 5    | DynamoDB                          |
+    /// | 124  | timed out                         |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TigerClawError::Assertion(_) => 1,
+            TigerClawError::Config(_) | TigerClawError::ReadOnly(_) => 2,
+            TigerClawError::Auth(_) => 3,
+            TigerClawError::Http { .. }
+            | TigerClawError::Request(_)
+            | TigerClawError::Decode { .. } => 4,
+            TigerClawError::DynamoDb(_) => 5,
+            TigerClawError::TimedOut(_) => 124,
+        }
+    }
+}
+
+impl fmt::Display for TigerClawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TigerClawError::Config(message) => write!(f, "Configuration error: {}", message),
+            TigerClawError::Auth(message) => write!(f, "Authentication failed: {}", message),
+            TigerClawError::Http { url, status, body } if body.is_empty() => {
+                write!(f, "Unexpected status {} from {}", status, url)
+            }
+            TigerClawError::Http { url, status, body } => {
+                write!(f, "Unexpected status {} from {}: {}", status, url, body)
+            }
+            TigerClawError::Request(e) => write!(f, "Request error: {}", e),
+            TigerClawError::TimedOut(url) => write!(f, "Timed out waiting for {}", url),
+            TigerClawError::Decode { url, message } => {
+                write!(f, "Failed to decode response from {}: {}", url, message)
+            }
+            TigerClawError::DynamoDb(e) => write!(f, "DynamoDB error: {}", e),
+            TigerClawError::ReadOnly(message) => write!(f, "{}", message),
+            TigerClawError::Assertion(message) => write!(f, "Check failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TigerClawError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TigerClawError::Request(e) => Some(e),
+            TigerClawError::DynamoDb(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for TigerClawError {
+    fn from(error: reqwest::Error) -> Self {
+        let url = error
+            .url()
+            .map(|url| url.to_string())
+            .unwrap_or_else(|| "request".to_string());
+        if error.is_timeout() {
+            TigerClawError::TimedOut(url)
+        } else if error.is_decode() {
+            TigerClawError::Decode {
+                url,
+                message: error.to_string(),
+            }
+        } else {
+            TigerClawError::Request(error)
+        }
+    }
+}
+
+impl From<aws_sdk_dynamodb::Error> for TigerClawError {
+    fn from(error: aws_sdk_dynamodb::Error) -> Self {
+        TigerClawError::DynamoDb(Box::new(error))
+    }
+}
+
+/// Lets `?` go straight from an SDK call result to `TigerClawError`
+impl<E, R> From<SdkError<E, R>> for TigerClawError
+where
+    aws_sdk_dynamodb::Error: From<SdkError<E, R>>,
+{
+    fn from(error: SdkError<E, R>) -> Self {
+        TigerClawError::from(aws_sdk_dynamodb::Error::from(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::execute;
+
+    #[tokio::test]
+    async fn responses_keep_status_and_body() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/broken")
+            .with_status(500)
+            .with_body("merchant 1 is locked")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/forbidden")
+            .with_status(403)
+            .create_async()
+            .await;
+
+        let client = reqwest::Client::new();
+        let response = execute(client.get(format!("{}/broken", server.url())))
+            .await
+            .unwrap();
+        let error = TigerClawError::from_response(&response);
+        assert_eq!(error.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(error.to_string().ends_with(": merchant 1 is locked"));
+        assert_eq!(error.exit_code(), 4);
+
+        let response = execute(client.get(format!("{}/forbidden", server.url())))
+            .await
+            .unwrap();
+        let error = TigerClawError::from_response(&response);
+        assert!(matches!(error, TigerClawError::Auth(_)));
+        assert_eq!(error.exit_code(), 3);
+    }
+
+    #[test]
+    fn exit_codes_separate_bugs_from_setup_problems() {
+        assert_eq!(TigerClawError::Assertion("x".to_string()).exit_code(), 1);
+        assert_eq!(TigerClawError::Config("x".to_string()).exit_code(), 2);
+        assert_eq!(TigerClawError::TimedOut("x".to_string()).exit_code(), 124);
+    }
+}
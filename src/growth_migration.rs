@@ -0,0 +1,485 @@
+use crate::auth::AuthProvider;
+use crate::base_url;
+use crate::config::GlobalConfig;
+use crate::error::TigerClawError;
+use crate::guardrails::Guardrails;
+use crate::health::{HealthStatus, Service};
+use crate::request::{self, BufferedResponse, send_with_retry};
+use crate::retry::{Idempotency, RetryPolicy};
+use crate::terms_and_conditions::Terms;
+use crate::timeouts::{self, ServiceTimeouts};
+use log::info;
+use reqwest::{RequestBuilder, StatusCode, Url};
+use std::sync::Arc;
+
+/// Client for growth-account-migration-service.
+///
+/// Built once per run so every call shares the same connection pool, base URL
+/// and auth provider
+pub struct GrowthMigrationClient {
+    http: reqwest::Client,
+    base_url: Url,
+    auth: Arc<dyn AuthProvider>,
+    retry: RetryPolicy,
+    guardrails: Guardrails,
+}
+
+impl GrowthMigrationClient {
+    pub fn new(
+        globals: &GlobalConfig,
+        auth: Arc<dyn AuthProvider>,
+    ) -> Result<Self, TigerClawError> {
+        Ok(GrowthMigrationClient {
+            http: timeouts::http_client(ServiceTimeouts::default())
+                .map_err(TigerClawError::Config)?,
+            base_url: globals
+                .growth_migration_base_url()
+                .map_err(TigerClawError::Config)?,
+            auth,
+            retry: RetryPolicy::default(),
+            guardrails: Guardrails::local(globals.read_only),
+        })
+    }
+
+    /// Check mutating calls against the run's guardrails instead of only
+    /// `read_only`. In a dry run they are printed and not sent
+    pub fn with_guardrails(mut self, guardrails: Guardrails) -> Self {
+        self.guardrails = guardrails;
+        self
+    }
+
+    /// Retry transient failures with `retry` instead of the default policy
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Use the service's configured timeouts instead of the defaults
+    pub fn with_timeouts(mut self, timeouts: ServiceTimeouts) -> Result<Self, TigerClawError> {
+        self.http = timeouts::http_client(timeouts).map_err(TigerClawError::Config)?;
+        Ok(self)
+    }
+
+    /// Spring actuator health, doesn't need credentials. Fails unless the
+    /// service reports UP
+    pub async fn health(&self) -> Result<(), TigerClawError> {
+        let url = self.url("actuator/health");
+        if self.dry_run() {
+            info!("Dry run, sending GET {}", url);
+        }
+        let response = request::execute(self.http.get(url))
+            .await
+            .map_err(TigerClawError::from)?;
+        if response.status() != StatusCode::OK {
+            return Err(TigerClawError::from_response(&response));
+        }
+        response
+            .json::<HealthStatus>()?
+            .check(Service::GrowthMigration)
+    }
+
+    /// Runs a single orchestration step for the SAS advertiser
+    pub async fn execute_step(
+        &self,
+        external_id: i32,
+        step: &str,
+    ) -> Result<BufferedResponse, TigerClawError> {
+        let url = self.url(&format!(
+            "migrate/sas/advertiser/{}/execute-step/{}",
+            external_id, step
+        ));
+        if !self.guardrails.allow_external_id(
+            &format!("execute step {}", step),
+            external_id,
+            &format!("POST {}", url),
+        )? {
+            return Ok(BufferedResponse::not_sent(url));
+        }
+        // Re-running a step that already started would start it twice
+        self.send(
+            || self.http.post(url.clone()),
+            &[StatusCode::OK],
+            Idempotency::NonIdempotent,
+        )
+        .await
+    }
+
+    /// Forces the advertiser's migration status, bypassing the orchestrator
+    pub async fn force_status(
+        &self,
+        external_id: i32,
+        status: &str,
+    ) -> Result<BufferedResponse, TigerClawError> {
+        let url = self.url(&format!("migrate/sas/advertiser/{}/status", external_id));
+        let request_body = serde_json::json!({ "force_status": status });
+        if !self.guardrails.allow_external_id(
+            "force the migration status",
+            external_id,
+            &format!("PATCH {} {}", url, request_body),
+        )? {
+            return Ok(BufferedResponse::not_sent(url));
+        }
+        self.send(
+            || self.http.patch(url.clone()).json(&request_body),
+            &[StatusCode::OK, StatusCode::ACCEPTED, StatusCode::NO_CONTENT],
+            Idempotency::Idempotent,
+        )
+        .await
+    }
+
+    /// Fetches the terms and conditions recorded for an Awin advertiser
+    pub async fn terms(&self, advertiser_id: i32) -> Result<Terms, TigerClawError> {
+        let url = self.url(&format!("terms/sas/advertiser/awin/{}", advertiser_id));
+        let response = self
+            .send(
+                || self.http.get(url.clone()),
+                &[StatusCode::OK],
+                Idempotency::Idempotent,
+            )
+            .await?;
+        response.json::<Terms>()
+    }
+
+    /// Locks the SAS merchant down so nothing changes while it is migrated
+    pub async fn lockdown(&self, merchant_id: i32) -> Result<BufferedResponse, TigerClawError> {
+        self.merchant_lock("lock merchants", "lockdown", merchant_id)
+            .await
+    }
+
+    /// Locks the SAS merchant's fees
+    pub async fn fee_lock(&self, merchant_id: i32) -> Result<BufferedResponse, TigerClawError> {
+        self.merchant_lock("fee lock merchants", "feelock", merchant_id)
+            .await
+    }
+
+    /// Lifts the lockdown, returning a test merchant to normal after a run
+    pub async fn unlock(&self, merchant_id: i32) -> Result<BufferedResponse, TigerClawError> {
+        self.merchant_lock("unlock merchants", "unlock", merchant_id)
+            .await
+    }
+
+    /// Lifts the fee lock
+    pub async fn fee_unlock(&self, merchant_id: i32) -> Result<BufferedResponse, TigerClawError> {
+        self.merchant_lock("fee unlock merchants", "feeunlock", merchant_id)
+            .await
+    }
+
+    /// POSTs to one of the `sasMigrationApi` lock endpoints, any 2xx is accepted
+    async fn merchant_lock(
+        &self,
+        action: &str,
+        endpoint: &str,
+        merchant_id: i32,
+    ) -> Result<BufferedResponse, TigerClawError> {
+        let url = self.url(&format!("sasMigrationApi/{}/{}", endpoint, merchant_id));
+        if !self
+            .guardrails
+            .allow_external_id(action, merchant_id, &format!("POST {}", url))?
+        {
+            return Ok(BufferedResponse::not_sent(url));
+        }
+        self.send(
+            || self.http.post(url.clone()),
+            &[],
+            Idempotency::NonIdempotent,
+        )
+        .await
+    }
+
+    /// Whether mutating calls are printed instead of sent
+    pub fn dry_run(&self) -> bool {
+        self.guardrails.dry_run()
+    }
+
+    fn url(&self, endpoint_path: &str) -> Url {
+        base_url::join(&self.base_url, endpoint_path)
+    }
+
+    /// Sends the request and turns any status outside `accepted` into a `TigerClawError`.
+    /// An empty `accepted` list accepts any 2xx status. Mutations never get here
+    /// in a dry run, so whatever is sent then is read only
+    async fn send<F>(
+        &self,
+        build_request: F,
+        accepted: &[StatusCode],
+        idempotency: Idempotency,
+    ) -> Result<BufferedResponse, TigerClawError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        if self.dry_run() {
+            info!("Dry run, sending {}", request::describe(&build_request));
+        }
+        let response = send_with_retry(self.auth.as_ref(), &self.retry, idempotency, build_request)
+            .await
+            .map_err(TigerClawError::from)?;
+
+        let status = response.status();
+        let is_accepted = if accepted.is_empty() {
+            status.is_success()
+        } else {
+            accepted.contains(&status)
+        };
+        if is_accepted {
+            return Ok(response);
+        }
+
+        Err(TigerClawError::from_response(&response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::StaticApiKey;
+    use crate::config::GuardrailConfig;
+    use crate::retry::RetryConfig;
+    use crate::setup::Environment;
+    use mockito::Matcher;
+
+    fn client(server: &mockito::Server, read_only: bool) -> GrowthMigrationClient {
+        let host_with_port = server.host_with_port();
+        let (host, port) = host_with_port.rsplit_once(':').unwrap();
+        // Old style url and port fields, to cover the backward compatible path
+        let globals = GlobalConfig {
+            environment: "local".to_string(),
+            base_growth_migration_url: Some(format!("http://{}/", host)),
+            base_growth_migration_port: Some(port.parse().unwrap()),
+            read_only,
+            ..GlobalConfig::default()
+        };
+        GrowthMigrationClient::new(
+            &globals,
+            Arc::new(StaticApiKey::new(
+                "Authorization",
+                "Bearer growth-client-test",
+            )),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn execute_step_posts_to_step_endpoint() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/migrate/sas/advertiser/44911/execute-step/VALID")
+            .match_header("authorization", "Bearer growth-client-test")
+            .with_status(200)
+            .create_async()
+            .await;
+
+        client(&server, false)
+            .execute_step(44911, "VALID")
+            .await
+            .unwrap();
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn force_status_patches_with_port_and_body() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("PATCH", "/migrate/sas/advertiser/44911/status")
+            .match_body(Matcher::Json(
+                serde_json::json!({ "force_status": "INIT_DONE" }),
+            ))
+            .with_status(204)
+            .create_async()
+            .await;
+
+        client(&server, false)
+            .force_status(44911, "INIT_DONE")
+            .await
+            .unwrap();
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn not_found_keeps_status_and_body() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/migrate/sas/advertiser/1/execute-step/INIT")
+            .with_status(404)
+            .with_body(r#"{"timestamp":"now","status":404,"error":"Not Found","path":"/x"}"#)
+            .create_async()
+            .await;
+
+        let error = client(&server, false)
+            .execute_step(1, "INIT")
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+        assert!(error.to_string().contains("Not Found"));
+    }
+
+    #[tokio::test]
+    async fn mutations_are_refused_in_read_only_mode() {
+        let server = mockito::Server::new_async().await;
+        let client = client(&server, true);
+        assert!(matches!(
+            client.lockdown(44911).await,
+            Err(TigerClawError::ReadOnly(_))
+        ));
+        assert!(matches!(
+            client.force_status(44911, "COMPLETED").await,
+            Err(TigerClawError::ReadOnly(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn health_needs_the_service_up() {
+        let mut server = mockito::Server::new_async().await;
+        let down = server
+            .mock("GET", "/actuator/health")
+            .with_status(503)
+            .with_body(r#"{"status": "DOWN"}"#)
+            .create_async()
+            .await;
+        let client = client(&server, false);
+        assert!(matches!(
+            client.health().await,
+            Err(TigerClawError::Http { .. })
+        ));
+        down.remove_async().await;
+
+        server
+            .mock("GET", "/actuator/health")
+            .with_status(200)
+            .with_body(r#"{"status": "UP"}"#)
+            .create_async()
+            .await;
+        client.health().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unlocks_post_to_their_endpoints() {
+        let mut server = mockito::Server::new_async().await;
+        let unlock = server
+            .mock("POST", "/sasMigrationApi/unlock/44911")
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        let fee_unlock = server
+            .mock("POST", "/sasMigrationApi/feeunlock/44911")
+            .with_status(204)
+            .expect(1)
+            .create_async()
+            .await;
+        let client = client(&server, false);
+        client.unlock(44911).await.unwrap();
+        client.fee_unlock(44911).await.unwrap();
+        unlock.assert_async().await;
+        fee_unlock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn guardrails_refuse_mutations_before_sending() {
+        let mut server = mockito::Server::new_async().await;
+        let lockdown = server
+            .mock("POST", Matcher::Any)
+            .expect(0)
+            .create_async()
+            .await;
+        let guardrails = Guardrails::new(
+            Environment::Staging,
+            false,
+            false,
+            &GuardrailConfig::default(),
+        );
+        let client = client(&server, false).with_guardrails(guardrails);
+        assert!(matches!(
+            client.lockdown(242424).await,
+            Err(TigerClawError::ReadOnly(_))
+        ));
+        assert!(matches!(
+            client.execute_step(242424, "VALID").await,
+            Err(TigerClawError::ReadOnly(_))
+        ));
+        lockdown.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn dry_runs_send_reads_and_print_mutations() {
+        let mut server = mockito::Server::new_async().await;
+        let posts = server
+            .mock("POST", Matcher::Any)
+            .expect(0)
+            .create_async()
+            .await;
+        let patches = server
+            .mock("PATCH", Matcher::Any)
+            .expect(0)
+            .create_async()
+            .await;
+        let terms = server
+            .mock("GET", "/terms/sas/advertiser/awin/424242")
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        // Guardrail refusals are printed too, instead of stopping the run
+        let guardrails = Guardrails::new(
+            Environment::Staging,
+            false,
+            false,
+            &GuardrailConfig::default(),
+        )
+        .with_dry_run(true);
+        let client = client(&server, false).with_guardrails(guardrails);
+
+        let response = client.execute_step(242424, "VALID").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(client.force_status(242424, "INIT_DONE").await.is_ok());
+        assert!(client.lockdown(242424).await.is_ok());
+        assert!(client.fee_lock(242424).await.is_ok());
+        // The empty body fails to decode, only that it was sent matters here
+        let _ = client.terms(424242).await;
+        posts.assert_async().await;
+        patches.assert_async().await;
+        terms.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn only_idempotent_calls_are_retried_on_gateway_errors() {
+        let mut server = mockito::Server::new_async().await;
+        let step = server
+            .mock("POST", "/migrate/sas/advertiser/1/execute-step/VALID")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        let terms_unavailable = server
+            .mock("GET", "/terms/sas/advertiser/awin/2")
+            .with_status(502)
+            .expect(1)
+            .create_async()
+            .await;
+        let terms = server
+            .mock("GET", "/terms/sas/advertiser/awin/2")
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        let client = client(&server, false).with_retry(RetryPolicy::new(&RetryConfig {
+            initial_backoff_ms: 1,
+            ..RetryConfig::default()
+        }));
+
+        assert!(matches!(
+            client.execute_step(1, "VALID").await,
+            Err(TigerClawError::Http {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                ..
+            })
+        ));
+        // The empty body fails to decode, only the retry matters here
+        assert!(matches!(
+            client.terms(2).await,
+            Err(TigerClawError::Decode { .. })
+        ));
+        step.assert_async().await;
+        terms_unavailable.assert_async().await;
+        terms.assert_async().await;
+    }
+}
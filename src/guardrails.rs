@@ -0,0 +1,361 @@
+use crate::config::GuardrailConfig;
+use crate::error::TigerClawError;
+use crate::setup::Environment;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+/// What a typed production confirmation has to match
+const PRODUCTION_CONFIRMATION: &str = "production";
+
+/// Decides which mutating calls a run may make. Execute-step, force status,
+/// the merchant locks and deletes all check it before sending anything:
+///
+/// - read-only runs refuse every mutation
+/// - outside local and dev mutations need `--allow-mutations`
+/// - production also needs the operator to type a confirmation, see `confirm`
+/// - with a `[guardrails]` allowlist for the environment only those IDs may
+///   be changed
+/// - a dry run sends none of them, see `allow_external_id`
+#[derive(Debug, Clone)]
+pub struct Guardrails {
+    environment: Environment,
+    read_only: bool,
+    allow_mutations: bool,
+    dry_run: bool,
+    confirmed: bool,
+    allowed_external_ids: Option<Vec<i32>>,
+    allowed_advertiser_ids: Option<Vec<i32>>,
+}
+
+impl Guardrails {
+    pub fn new(
+        environment: Environment,
+        read_only: bool,
+        allow_mutations: bool,
+        config: &GuardrailConfig,
+    ) -> Self {
+        Guardrails {
+            environment,
+            read_only,
+            allow_mutations,
+            dry_run: false,
+            confirmed: false,
+            allowed_external_ids: allowlist(&config.allowed_external_ids, environment),
+            allowed_advertiser_ids: allowlist(&config.allowed_advertiser_ids, environment),
+        }
+    }
+
+    /// Only the read-only flag applies, for clients built outside a run
+    pub fn local(read_only: bool) -> Self {
+        Guardrails::new(
+            Environment::Local,
+            read_only,
+            false,
+            &GuardrailConfig::default(),
+        )
+    }
+
+    /// Print mutating calls instead of sending them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn environment(&self) -> Environment {
+        self.environment
+    }
+
+    /// Whether a typed confirmation is needed before any mutation
+    pub fn needs_confirmation(&self) -> bool {
+        self.environment == Environment::Production
+            && self.allow_mutations
+            && !self.read_only
+            && !self.dry_run
+            && !self.confirmed
+    }
+
+    /// Asks the operator to type `production` before mutations against
+    /// production are allowed. Anything else, or no input at all, fails the run
+    pub fn confirm(
+        &mut self,
+        input: &mut impl BufRead,
+        prompt: &mut impl Write,
+    ) -> Result<(), TigerClawError> {
+        if !self.needs_confirmation() {
+            return Ok(());
+        }
+        let _ = write!(
+            prompt,
+            "--allow-mutations lets this run change production advertisers. Type '{}' to continue: ",
+            PRODUCTION_CONFIRMATION
+        );
+        let _ = prompt.flush();
+        let mut answer = String::new();
+        input.read_line(&mut answer).map_err(|e| {
+            TigerClawError::Config(format!("Failed to read the confirmation: {}", e))
+        })?;
+        if answer.trim() != PRODUCTION_CONFIRMATION {
+            return Err(TigerClawError::Config(
+                "Production mutations were not confirmed, nothing was run".to_string(),
+            ));
+        }
+        self.confirmed = true;
+        Ok(())
+    }
+
+    /// Whether `operation`, a mutation of the SAS advertiser or merchant with
+    /// `external_id`, should be sent. A dry run never sends it and only prints
+    /// it, along with why the guardrails would refuse it
+    pub fn allow_external_id(
+        &self,
+        action: &str,
+        external_id: i32,
+        operation: &str,
+    ) -> Result<bool, TigerClawError> {
+        self.allow(self.check_external_id(action, external_id), operation)
+    }
+
+    /// `allow_external_id` for the Awin advertiser with `advertiser_id`
+    pub fn allow_advertiser_id(
+        &self,
+        action: &str,
+        advertiser_id: i32,
+        operation: &str,
+    ) -> Result<bool, TigerClawError> {
+        self.allow(self.check_advertiser_id(action, advertiser_id), operation)
+    }
+
+    fn allow(
+        &self,
+        checked: Result<(), TigerClawError>,
+        operation: &str,
+    ) -> Result<bool, TigerClawError> {
+        if !self.dry_run {
+            return checked.map(|_| true);
+        }
+        match checked {
+            Ok(()) => info!("Dry run, not sending {}", operation),
+            Err(error) => warn!(
+                "Dry run, not sending {}, it would be refused: {}",
+                operation, error
+            ),
+        }
+        Ok(false)
+    }
+
+    /// Checks a mutation of the SAS advertiser or merchant with `external_id`
+    pub fn check_external_id(&self, action: &str, external_id: i32) -> Result<(), TigerClawError> {
+        self.check(action)?;
+        check_allowlist(
+            action,
+            external_id,
+            self.allowed_external_ids.as_deref(),
+            "allowed_external_ids",
+            self.environment,
+        )
+    }
+
+    /// Checks a mutation of the Awin advertiser with `advertiser_id`
+    pub fn check_advertiser_id(
+        &self,
+        action: &str,
+        advertiser_id: i32,
+    ) -> Result<(), TigerClawError> {
+        self.check(action)?;
+        check_allowlist(
+            action,
+            advertiser_id,
+            self.allowed_advertiser_ids.as_deref(),
+            "allowed_advertiser_ids",
+            self.environment,
+        )
+    }
+
+    fn check(&self, action: &str) -> Result<(), TigerClawError> {
+        if self.read_only {
+            return Err(TigerClawError::ReadOnly(format!(
+                "Refusing to {} in read-only mode",
+                action
+            )));
+        }
+        let protected = !matches!(self.environment, Environment::Local | Environment::Dev);
+        if protected && !self.allow_mutations {
+            return Err(TigerClawError::ReadOnly(format!(
+                "Refusing to {} in {} without --allow-mutations",
+                action,
+                self.environment.name()
+            )));
+        }
+        if self.needs_confirmation() {
+            return Err(TigerClawError::ReadOnly(format!(
+                "Refusing to {} in production without a typed confirmation",
+                action
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The environment's entry, keys are environment names such as `staging` or
+/// `prod`
+fn allowlist(ids: &HashMap<String, Vec<i32>>, environment: Environment) -> Option<Vec<i32>> {
+    ids.iter()
+        .find(|(name, _)| Environment::from_name(name) == Some(environment))
+        .map(|(_, ids)| ids.clone())
+}
+
+fn check_allowlist(
+    action: &str,
+    id: i32,
+    allowed: Option<&[i32]>,
+    key: &str,
+    environment: Environment,
+) -> Result<(), TigerClawError> {
+    match allowed {
+        Some(allowed) if !allowed.contains(&id) => Err(TigerClawError::ReadOnly(format!(
+            "Refusing to {} for {}, it isn't in guardrails.{}.{}",
+            action,
+            id,
+            key,
+            environment.name()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(allowlist: &str) -> GuardrailConfig {
+        toml::from_str(allowlist).unwrap()
+    }
+
+    #[test]
+    fn mutations_outside_local_and_dev_need_allow_mutations() {
+        let none = GuardrailConfig::default();
+        assert!(
+            Guardrails::new(Environment::Dev, false, false, &none)
+                .check_external_id("force the migration status", 44911)
+                .is_ok()
+        );
+        assert!(matches!(
+            Guardrails::new(Environment::Dev, true, true, &none)
+                .check_external_id("force the migration status", 44911),
+            Err(TigerClawError::ReadOnly(_))
+        ));
+
+        let error = Guardrails::new(Environment::Staging, false, false, &none)
+            .check_external_id("lock merchants", 242424)
+            .unwrap_err();
+        assert!(error.to_string().contains("--allow-mutations"), "{}", error);
+        assert!(
+            Guardrails::new(Environment::Staging, false, true, &none)
+                .check_external_id("lock merchants", 242424)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn production_mutations_need_a_typed_confirmation() {
+        let none = GuardrailConfig::default();
+        let mut guardrails = Guardrails::new(Environment::Production, false, true, &none);
+        assert!(guardrails.needs_confirmation());
+        assert!(
+            guardrails
+                .check_external_id("execute step VALID", 1)
+                .is_err()
+        );
+
+        let mut prompt = Vec::new();
+        assert!(
+            guardrails
+                .confirm(&mut "yes\n".as_bytes(), &mut prompt)
+                .is_err()
+        );
+        assert!(
+            String::from_utf8(prompt)
+                .unwrap()
+                .contains("Type 'production'")
+        );
+        assert!(
+            guardrails
+                .check_external_id("execute step VALID", 1)
+                .is_err()
+        );
+
+        guardrails
+            .confirm(&mut "production\n".as_bytes(), &mut Vec::new())
+            .unwrap();
+        assert!(
+            guardrails
+                .check_external_id("execute step VALID", 1)
+                .is_ok()
+        );
+
+        // Without --allow-mutations production is read only and nothing is asked
+        let guardrails = Guardrails::new(Environment::Production, false, false, &none);
+        assert!(!guardrails.needs_confirmation());
+        assert!(
+            guardrails
+                .check_external_id("execute step VALID", 1)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn only_allowlisted_ids_of_the_environment_may_change() {
+        let config = config(
+            "[allowed_external_ids]\nstaging = [242424]\n[allowed_advertiser_ids]\nprod = [424242]\n",
+        );
+        let staging = Guardrails::new(Environment::Staging, false, true, &config);
+        assert!(staging.check_external_id("lock merchants", 242424).is_ok());
+        let error = staging
+            .check_external_id("lock merchants", 44911)
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("guardrails.allowed_external_ids.staging"),
+            "{}",
+            error
+        );
+        // No advertiser allowlist for staging
+        assert!(staging.check_advertiser_id("delete advertisers", 1).is_ok());
+
+        let dev = Guardrails::new(Environment::Dev, false, false, &config);
+        assert!(dev.check_external_id("lock merchants", 44911).is_ok());
+    }
+
+    #[test]
+    fn dry_runs_send_no_mutations_and_ask_for_nothing() {
+        let none = GuardrailConfig::default();
+        let dev = Guardrails::new(Environment::Dev, false, false, &none);
+        assert!(
+            dev.allow_external_id("lock merchants", 1, "POST /x")
+                .unwrap()
+        );
+
+        let dev = dev.with_dry_run(true);
+        assert!(
+            !dev.allow_external_id("lock merchants", 1, "POST /x")
+                .unwrap()
+        );
+        // Refusals are reported, not returned, so the rest can be reviewed
+        let staging = Guardrails::new(Environment::Staging, false, false, &none).with_dry_run(true);
+        assert!(
+            !staging
+                .allow_external_id("lock merchants", 1, "POST /x")
+                .unwrap()
+        );
+
+        let production =
+            Guardrails::new(Environment::Production, false, true, &none).with_dry_run(true);
+        assert!(!production.needs_confirmation());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::error::TigerClawError;
+use crate::suites::Suite;
+use futures::future::join_all;
+use log::{error, info, warn};
+use serde::Deserialize;
+
+/// A service the suites talk to, probed before any of them runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Service {
+    GrowthMigration,
+    SasDataImport,
+    DynamoDb,
+}
+
+impl Service {
+    pub const ALL: [Service; 3] = [
+        Service::GrowthMigration,
+        Service::SasDataImport,
+        Service::DynamoDb,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::GrowthMigration => "growth-account-migration-service",
+            Service::SasDataImport => "sas-data-import",
+            Service::DynamoDb => "DynamoDB",
+        }
+    }
+}
+
+/// Body of a Spring actuator health endpoint
+#[derive(Debug, Deserialize)]
+pub struct HealthStatus {
+    pub status: String,
+}
+
+impl HealthStatus {
+    pub fn check(&self, service: Service) -> Result<(), TigerClawError> {
+        if self.status == "UP" {
+            return Ok(());
+        }
+        Err(TigerClawError::Assertion(format!(
+            "{} status is {}",
+            service.name(),
+            self.status
+        )))
+    }
+}
+
+/// What probing each service found, before the suites ran or for the
+/// `health` command
+#[derive(Debug, Default)]
+pub struct ServiceHealth {
+    probed: Vec<(Service, Result<(), TigerClawError>)>,
+}
+
+impl ServiceHealth {
+    /// Probes every service the suites need, see `check_services`
+    pub async fn check<P, Fut>(suites: &[Suite], probe: P) -> Self
+    where
+        P: Fn(Service) -> Fut,
+        Fut: Future<Output = Result<(), TigerClawError>>,
+    {
+        let mut services: Vec<Service> = Vec::new();
+        for service in suites.iter().flat_map(|suite| suite.services()) {
+            if !services.contains(service) {
+                services.push(*service);
+            }
+        }
+        Self::check_services(&services, probe).await
+    }
+
+    /// Probes `services`, all at once. Auth and config errors say nothing
+    /// about the service itself, so those only warn and don't count as the
+    /// service being down
+    pub async fn check_services<P, Fut>(services: &[Service], probe: P) -> Self
+    where
+        P: Fn(Service) -> Fut,
+        Fut: Future<Output = Result<(), TigerClawError>>,
+    {
+        let probes = services.iter().map(|service| {
+            let result = probe(*service);
+            async move { (*service, result.await) }
+        });
+
+        let probed = join_all(probes).await;
+        for (service, result) in &probed {
+            match result {
+                Ok(()) => info!("{} is available", service.name()),
+                Err(error) if !is_down(error) => {
+                    warn!("Couldn't check {}: {}", service.name(), error)
+                }
+                Err(error) => error!("{} is unavailable: {}", service.name(), error),
+            }
+        }
+        ServiceHealth { probed }
+    }
+
+    /// Why `suite` can't run, naming the first service it needs that is down
+    pub fn blocked(&self, suite: Suite) -> Option<String> {
+        self.down()
+            .find(|(service, _)| suite.services().contains(service))
+            .map(|(service, error)| format!("service unavailable, {}: {}", service.name(), error))
+    }
+
+    /// One line per probed service, e.g. `  DynamoDB: down, ...`
+    pub fn summary(&self) -> String {
+        let mut summary = String::from("Service health:");
+        for (service, result) in &self.probed {
+            let state = match result {
+                Ok(()) => "up".to_string(),
+                Err(error) if !is_down(error) => format!("not checked, {}", error),
+                Err(error) => format!("down, {}", error),
+            };
+            summary.push_str(&format!("\n  {}: {}", service.name(), state));
+        }
+        summary
+    }
+
+    /// The first probe failure. Skipping the suites that needed the service
+    /// doesn't make the run pass
+    pub fn into_result(self) -> Result<(), TigerClawError> {
+        self.probed
+            .into_iter()
+            .filter_map(|(_, result)| result.err())
+            .find(is_down)
+            .map_or(Ok(()), Err)
+    }
+
+    /// Like `into_result`, but a service that couldn't be checked fails too,
+    /// for when checking the services is all there is to do
+    pub fn require_all_up(self) -> Result<(), TigerClawError> {
+        let mut errors: Vec<TigerClawError> = self
+            .probed
+            .into_iter()
+            .filter_map(|(_, result)| result.err())
+            .collect();
+        match errors.iter().position(is_down) {
+            Some(down) => Err(errors.swap_remove(down)),
+            None => errors.into_iter().next().map_or(Ok(()), Err),
+        }
+    }
+
+    fn down(&self) -> impl Iterator<Item = (Service, &TigerClawError)> {
+        self.probed
+            .iter()
+            .filter_map(|(service, result)| result.as_ref().err().map(|error| (*service, error)))
+            .filter(|(_, error)| is_down(error))
+    }
+}
+
+fn is_down(error: &TigerClawError) -> bool {
+    !matches!(error, TigerClawError::Auth(_) | TigerClawError::Config(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    #[tokio::test]
+    async fn only_services_that_are_down_block_their_suites() {
+        let health = ServiceHealth::check(&Suite::ALL, |service| async move {
+            match service {
+                Service::DynamoDb => Err(TigerClawError::Http {
+                    status: StatusCode::SERVICE_UNAVAILABLE,
+                    url: "dynamodb".to_string(),
+                    body: String::new(),
+                }),
+                Service::SasDataImport => Err(TigerClawError::Auth("no key".to_string())),
+                Service::GrowthMigration => Ok(()),
+            }
+        })
+        .await;
+
+        let reason = health.blocked(Suite::Orchestration).unwrap();
+        assert!(
+            reason.starts_with("service unavailable, DynamoDB:"),
+            "{}",
+            reason
+        );
+        assert_eq!(health.blocked(Suite::SasDataImport), None);
+        assert_eq!(health.blocked(Suite::MigrationApi), None);
+        assert_eq!(health.blocked(Suite::Terms), None);
+        assert!(health.blocked(Suite::DynamoDb).is_some());
+        assert!(matches!(
+            health.into_result(),
+            Err(TigerClawError::Http { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn every_service_is_reported_and_unchecked_ones_fail_the_health_command() {
+        let health = ServiceHealth::check_services(&Service::ALL, |service| async move {
+            match service {
+                Service::SasDataImport => Err(TigerClawError::Auth("no key".to_string())),
+                _ => Ok(()),
+            }
+        })
+        .await;
+
+        assert_eq!(
+            health.summary(),
+            "Service health:\n  growth-account-migration-service: up\n  sas-data-import: not checked, Authentication failed: no key\n  DynamoDB: up"
+        );
+        assert!(matches!(
+            health.require_all_up(),
+            Err(TigerClawError::Auth(_))
+        ));
+    }
+
+    #[test]
+    fn only_up_is_healthy() {
+        let up: HealthStatus = serde_json::from_str(r#"{"status": "UP"}"#).unwrap();
+        assert!(up.check(Service::GrowthMigration).is_ok());
+        let down: HealthStatus = serde_json::from_str(r#"{"status": "DOWN"}"#).unwrap();
+        let error = down.check(Service::GrowthMigration).unwrap_err();
+        assert!(error.to_string().contains("status is DOWN"), "{}", error);
+    }
+}
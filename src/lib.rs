@@ -1,11 +1,30 @@
 pub mod advertiser;
+pub mod auth;
+pub mod aws;
+pub mod base_url;
+pub mod commands;
 pub mod config;
+pub mod config_check;
+pub mod config_template;
+pub mod error;
+pub mod growth_migration;
+pub mod guardrails;
+pub mod health;
+pub mod logger;
+pub mod merchant;
 pub mod orchestration_status;
 pub mod orchestrator;
 pub mod publisher;
+pub mod redact;
 pub mod request;
+pub mod retry;
 pub mod sas_data_import;
+pub mod sas_data_import_client;
+pub mod sas_migration_api;
+pub mod sas_resources;
+pub mod secret;
 pub mod setup;
+pub mod suites;
 pub mod terms_and_conditions;
-pub mod sas_migration_api;
-pub mod merchant;
+pub mod timeouts;
+pub mod token;
@@ -0,0 +1,270 @@
+use crate::redact;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+tokio::task_local! {
+    static GROUP: LogGroup;
+}
+
+/// How log lines are written to stderr
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// `LEVEL message`, for people
+    #[default]
+    Text,
+    /// One JSON object per line, for CI log ingestion
+    Json,
+}
+
+/// Which levels are logged for which targets, parsed from `RUST_LOG` style
+/// directives such as `info,tiger_claw::request=debug`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    default: LevelFilter,
+    /// Module path prefixes and their levels, longest prefix wins
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Self {
+        let mut filter = Filter {
+            default: LevelFilter::Info,
+            directives: Vec::new(),
+        };
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    if let Ok(level) = level.trim().parse() {
+                        filter.directives.push((target.trim().to_string(), level));
+                    }
+                }
+                None => match directive.parse() {
+                    Ok(level) => filter.default = level,
+                    // A bare module path means everything from it
+                    Err(_) => filter
+                        .directives
+                        .push((directive.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+        filter
+            .directives
+            .sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        filter
+    }
+
+    /// The filter for a run: `RUST_LOG` when set, otherwise info. `-v` turns on
+    /// debug output for this crate, including every HTTP exchange, and `-q`
+    /// keeps only warnings and errors
+    pub fn from_args(rust_log: Option<&str>, verbose: bool, quiet: bool) -> Self {
+        let mut filter = Filter::parse(rust_log.unwrap_or("info"));
+        if quiet {
+            filter.default = LevelFilter::Warn;
+            filter.directives.clear();
+        } else if verbose && filter.level_for("tiger_claw") < LevelFilter::Debug {
+            filter
+                .directives
+                .insert(0, ("tiger_claw".to_string(), LevelFilter::Debug));
+            filter
+                .directives
+                .sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        }
+        filter
+    }
+
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|(prefix, _)| {
+                target == prefix
+                    || target
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// Lines logged by one suite while it runs alongside others. They are held
+/// back and written out in one block once it finishes, so the output of
+/// concurrent suites doesn't interleave
+#[derive(Debug, Clone)]
+pub struct LogGroup {
+    name: String,
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+impl LogGroup {
+    pub fn new(name: impl Into<String>) -> Self {
+        LogGroup {
+            name: name.into(),
+            lines: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Runs `future` with everything it logs held in this group
+    pub async fn scope<F: Future>(&self, future: F) -> F::Output {
+        GROUP.scope(self.clone(), future).await
+    }
+
+    /// Writes the held lines to stderr in one go
+    pub fn flush(&self) {
+        let lines = std::mem::take(&mut *self.lock());
+        let mut stderr = std::io::stderr().lock();
+        for line in lines {
+            let _ = writeln!(stderr, "{}", line);
+        }
+    }
+
+    fn push(&self, line: String) {
+        self.lock().push(line);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<String>> {
+        self.lines.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Writes every record to stderr with registered secrets masked, so nothing
+/// logged anywhere in the crate can leak a credential
+pub struct Logger {
+    filter: Filter,
+    format: LogFormat,
+}
+
+impl Logger {
+    pub fn new(filter: Filter, format: LogFormat) -> Self {
+        Logger { filter, format }
+    }
+
+    fn format(&self, record: &Record, group: Option<&LogGroup>) -> String {
+        let message = redact::redact(&record.args().to_string());
+        match (self.format, group) {
+            (LogFormat::Text, None) => format!("{:<5} {}", record.level(), message),
+            (LogFormat::Text, Some(group)) => {
+                format!("{:<5} [{}] {}", record.level(), group.name, message)
+            }
+            (LogFormat::Json, group) => {
+                let mut line = serde_json::json!({
+                    "timestamp": chrono::Utc::now().to_rfc3339(),
+                    "level": record.level().as_str(),
+                    "target": record.target(),
+                    "message": message,
+                });
+                if let Some(group) = group {
+                    line["suite"] = group.name.clone().into();
+                }
+                line.to_string()
+            }
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let grouped = GROUP.try_with(|group| group.push(self.format(record, Some(group))));
+        if grouped.is_err() {
+            let _ = writeln!(std::io::stderr().lock(), "{}", self.format(record, None));
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Installs the logger for the process, only the first call has any effect
+pub fn init(filter: Filter, format: LogFormat) {
+    let max_level = filter.max_level();
+    let logger = Box::leak(Box::new(Logger::new(filter, format)));
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives_pick_the_longest_matching_target() {
+        let filter = Filter::parse("warn,tiger_claw=info,tiger_claw::request=debug");
+        assert_eq!(filter.level_for("hyper::proto"), LevelFilter::Warn);
+        assert_eq!(
+            filter.level_for("tiger_claw::orchestrator"),
+            LevelFilter::Info
+        );
+        assert_eq!(filter.level_for("tiger_claw::request"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("tiger_clawed"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn flags_adjust_the_environment_filter() {
+        let verbose = Filter::from_args(None, true, false);
+        assert_eq!(verbose.level_for("tiger_claw::request"), LevelFilter::Debug);
+        assert_eq!(verbose.level_for("hyper"), LevelFilter::Info);
+
+        let trace = Filter::from_args(Some("trace"), true, false);
+        assert_eq!(trace.level_for("tiger_claw"), LevelFilter::Trace);
+
+        let quiet = Filter::from_args(Some("tiger_claw=debug"), false, true);
+        assert_eq!(quiet.level_for("tiger_claw"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn json_lines_are_redacted() {
+        redact::register_secret("logger-test-secret");
+        let logger = Logger::new(Filter::parse("info"), LogFormat::Json);
+        let line = logger.format(
+            &Record::builder()
+                .args(format_args!("token logger-test-secret rejected"))
+                .level(log::Level::Error)
+                .target("tiger_claw::auth")
+                .build(),
+            None,
+        );
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["level"], "ERROR");
+        assert_eq!(json["target"], "tiger_claw::auth");
+        assert_eq!(json["message"], "token **** rejected");
+    }
+
+    #[tokio::test]
+    async fn grouped_lines_are_held_until_flushed() {
+        let logger = Logger::new(Filter::parse("info"), LogFormat::Text);
+        let group = LogGroup::new("sas_data_import");
+        group
+            .scope(async {
+                logger.log(
+                    &Record::builder()
+                        .args(format_args!("health check passed"))
+                        .level(log::Level::Info)
+                        .target("tiger_claw::sas_data_import")
+                        .build(),
+                );
+            })
+            .await;
+        assert_eq!(
+            *group.lock(),
+            ["INFO  [sas_data_import] health check passed"]
+        );
+        group.flush();
+        assert!(group.lock().is_empty());
+    }
+}
@@ -1,68 +1,251 @@
 use clap::Parser;
+use log::{error, info, warn};
 use tiger_claw::{
-    config, orchestrator, sas_data_import, sas_migration_api, setup::{get_token_and_environment, Args}
+    advertiser,
+    auth::AuthProviders,
+    commands::{
+        self, Command, ConfigCommand, growth_migration_client, lock_verifier,
+        sas_data_import_client,
+    },
+    config::{LayeredConfig, TestConfig},
+    config_check, config_template,
+    error::TigerClawError,
+    guardrails::Guardrails,
+    health::ServiceHealth,
+    logger::{self, Filter},
+    orchestration_status::StepDescriptor,
+    orchestrator, println_redacted,
+    retry::RetryPolicy,
+    sas_data_import, sas_migration_api,
+    setup::{Args, check_config_environment, get_token_and_environment},
+    suites::{self, Suite, SuitePlan},
+    terms_and_conditions,
 };
-use log::{info, warn, error};
 
 #[tokio::main]
 async fn main() {
-    println!("Tiger Claw - SAS Migration Tool");
     let args = Args::parse();
-    let (token, _environment) = get_token_and_environment(&args).await;
-    let mut config = config::load_test_config(&args.get_config_path()).unwrap();
-    if config::validate_test_config(&config).is_err() {
-        error!("Configuration validation failed, exiting.");
-        return;
+    let rust_log = std::env::var("RUST_LOG").ok();
+    logger::init(
+        Filter::from_args(rust_log.as_deref(), args.verbose, args.quiet),
+        args.log_format,
+    );
+    info!("Tiger Claw - SAS Migration Tool");
+    // The first failure decides the exit code, see TigerClawError::exit_code
+    if let Err(error) = run(args).await {
+        error!("{}", error);
+        log::logger().flush();
+        std::process::exit(error.exit_code());
     }
-    println!("Configuration validation passed. loaded configuration from: {} and running ", args.get_config_path());
+}
+
+async fn run(args: Args) -> Result<(), TigerClawError> {
+    let command = args.command.clone().unwrap_or(Command::Run);
+    let environment = args.environment()?;
+    if let Command::Config(ConfigCommand::Init { output, force }) = &command {
+        let path = config_template::write(environment, output.as_deref(), *force)?;
+        info!(
+            "Wrote a starter {} configuration to {}, fill in the IDs to test with and check it with: tiger-claw -n {} -c {} config check",
+            environment.name(),
+            path.display(),
+            environment.name(),
+            path.display()
+        );
+        return Ok(());
+    }
+    let layered = LayeredConfig::load(&args.get_config_path())?;
+    if command == Command::Config(ConfigCommand::Show) {
+        println_redacted!("{}", layered.show());
+        return Ok(());
+    }
+    layered.validate()?;
+    let mut config = layered.config;
+    check_config_environment(environment, &config.globals)?;
+    if command == Command::Config(ConfigCommand::Check) {
+        info!("Configuration {} is valid", args.get_config_path());
+        return Ok(());
+    }
+    if args.read_only {
+        config.globals.read_only = true;
+    }
+    let mut guardrails = Guardrails::new(
+        environment,
+        config.globals.read_only,
+        args.allow_mutations,
+        &config.guardrails,
+    )
+    .with_dry_run(args.dry_run);
+    if args.dry_run {
+        info!(
+            "Dry run, read only calls are sent, every mutating call is printed instead and treated as if it succeeded"
+        );
+    }
+    guardrails.confirm(&mut std::io::stdin().lock(), &mut std::io::stderr())?;
+    let (tokens, _environment) = get_token_and_environment(&args, &config.auth)?;
+    let auth_providers = AuthProviders::new(&config.auth, tokens);
+    let retry = RetryPolicy::new(&config.retry);
+    info!(
+        "Configuration validation passed. loaded configuration from: {} and running ",
+        args.get_config_path()
+    );
 
     // if we've passed in advertiser_id on top of the config file throw a warning
     if args.advertiser_id.is_some() {
-        warn!("WARNING: advertiser_id passed in via command line AND the toml configuration file, this will override the config file");
+        warn!(
+            "advertiser_id passed in via command line AND the toml configuration file, this will override the config file"
+        );
         config.globals.advertiser_id = args.advertiser_id;
     }
 
     if args.external_id.is_some() {
-        warn!("WARNING: external_id passed in via command line AND the toml configuration file, this will override the config file");
+        warn!(
+            "external_id passed in via command line AND the toml configuration file, this will override the config file"
+        );
         config.globals.external_id = args.external_id;
     }
 
-    // orchestration step should run if the step is enabled AND step_to_run is not
-    // an empty string
-    if config.orchestration.enabled && !config.orchestration.step_to_run.is_empty() {
-        println!("Orchestration step is enabled, running...");
-        orchestrator::run(
-            &token,
-            &config
-        )
-        .await;
-    }
-    else{
-        println!("Orchestration step is not enabled or no step specified, skipping.");
+    if let Some(steps) = &args.migration_step {
+        StepDescriptor::parse_list(steps).map_err(TigerClawError::Config)?;
+        info!(
+            "Running step {} from --step instead of orchestration.step_to_run",
+            steps
+        );
+        config.orchestration.step_to_run = steps.clone();
+        config.orchestration.enabled = true;
     }
 
-    // run sas_data_import step if enabled in config
-    if config.sas_data_import.enabled {
-        println!("SAS Data Import step is enabled, running...");
-        sas_data_import::run(&config).await;
-    }
-    else {
-        println!("SAS Data Import step is not enabled in the configuration, skipping.");
+    let run_deadline = config.timeouts.run_deadline();
+    let deadline = run_deadline.map(|run_deadline| tokio::time::Instant::now() + run_deadline);
+    match (command, run_deadline.zip(deadline)) {
+        // Suites enforce the deadline themselves so each one's output is
+        // still flushed when it times out
+        (Command::Run, _) => {
+            run_suites(&config, &auth_providers, &retry, &guardrails, deadline).await
+        }
+        (command, Some((run_deadline, deadline))) => tokio::time::timeout_at(
+            deadline,
+            commands::run(&command, config, &auth_providers, &retry, &guardrails),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(TigerClawError::TimedOut(format!(
+                "the run to finish within its {}s deadline, remaining checks were not run",
+                run_deadline.as_secs()
+            )))
+        }),
+        (command, None) => {
+            commands::run(&command, config, &auth_providers, &retry, &guardrails).await
+        }
     }
+}
 
-    if config.migration_api.enabled {
-        println!("Migration API step is enabled, running...");
-        if config.globals.external_id.is_none() {
-            panic!("external_id is required for migration_api");
+/// Runs every enabled suite as `[suites]` plans it, once the services they
+/// need have been checked. A suite whose dependencies didn't all pass or
+/// whose service is down is skipped, the others still run after a failure.
+/// The first failure is returned. Suites still running at `deadline` time
+/// out, the ones not started yet are skipped
+async fn run_suites(
+    config: &TestConfig,
+    auth_providers: &AuthProviders,
+    retry: &RetryPolicy,
+    guardrails: &Guardrails,
+    deadline: Option<tokio::time::Instant>,
+) -> Result<(), TigerClawError> {
+    let plan = SuitePlan::new(&config.suites)
+        .map_err(|problems| TigerClawError::Config(config_check::report(&problems)))?;
+    let health = if config.suites.health_checks {
+        let enabled: Vec<Suite> = plan
+            .order()
+            .iter()
+            .copied()
+            .filter(|suite| suite.enabled(config))
+            .collect();
+        let check = ServiceHealth::check(&enabled, |service| {
+            commands::probe(service, config, auth_providers, retry, guardrails)
+        });
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, check)
+                .await
+                .map_err(|_| {
+                    TigerClawError::TimedOut(
+                        "the service health checks to finish before the run deadline".to_string(),
+                    )
+                })?,
+            None => check.await,
         }
-        sas_migration_api::run(
-            &config.globals.external_id.unwrap(),
-            &token,
-            &config
-        )
-        .await;
+    } else {
+        ServiceHealth::default()
+    };
+    let report = suites::run(&plan, config, &health, deadline, |suite| {
+        run_suite(suite, config, auth_providers, retry, guardrails)
+    })
+    .await;
+    for line in report.summary() {
+        info!("{}", line);
     }
-    else {
-        println!("Migration API step is not enabled in the configuration, skipping.");
+    health.into_result().and(report.into_result())
+}
+
+async fn run_suite(
+    suite: Suite,
+    config: &TestConfig,
+    auth_providers: &AuthProviders,
+    retry: &RetryPolicy,
+    guardrails: &Guardrails,
+) -> Result<(), TigerClawError> {
+    match suite {
+        Suite::Orchestration => {
+            let client = growth_migration_client(config, auth_providers, retry, guardrails)
+                .await
+                .inspect_err(|error| error!("Skipping orchestration: {}", error))?;
+            orchestrator::run(&client, config, retry, guardrails.environment()).await
+        }
+        Suite::SasDataImport => {
+            let client = sas_data_import_client(config, auth_providers, retry, guardrails)
+                .await
+                .inspect_err(|error| error!("Skipping SAS Data Import: {}", error))?;
+            sas_data_import::run(&client, config).await
+        }
+        Suite::Terms => {
+            let Some(advertiser_id) = config.globals.advertiser_id else {
+                return Err(TigerClawError::Config(
+                    "advertiser_id is required for terms".to_string(),
+                ));
+            };
+            let client = growth_migration_client(config, auth_providers, retry, guardrails)
+                .await
+                .inspect_err(|error| error!("Skipping terms: {}", error))?;
+            terms_and_conditions::run(&client, &advertiser_id).await
+        }
+        Suite::DynamoDb => {
+            advertiser::run(
+                config,
+                retry,
+                guardrails.environment(),
+                guardrails.dry_run(),
+            )
+            .await
+        }
+        Suite::MigrationApi => {
+            let Some(external_id) = config.globals.external_id else {
+                return Err(TigerClawError::Config(
+                    "external_id is required for migration_api".to_string(),
+                ));
+            };
+            let client = growth_migration_client(config, auth_providers, retry, guardrails)
+                .await
+                .inspect_err(|error| error!("Skipping Migration API: {}", error))?;
+            let merchants = lock_verifier(config, auth_providers, retry, guardrails).await?;
+            let mut outcome = sas_migration_api::run(&external_id, &client, &merchants).await;
+            if config.migration_api.unlock_after_run {
+                info!(
+                    "Unlocking merchant {} again, migration_api.unlock_after_run is set",
+                    external_id
+                );
+                outcome =
+                    outcome.and(sas_migration_api::unlock(&external_id, &client, &merchants).await);
+            }
+            outcome
+        }
     }
 }
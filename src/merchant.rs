@@ -44,6 +44,13 @@ pub struct Merchant {
     pub credit_limit: Option<f64>,
     #[serde(rename = "advertiserPlatformPlan")]
     pub advertiser_platform_plan: String,
+    /// Set by the migration API's lockdown. Older service versions leave it
+    /// out, then the locks can't be verified and the lock commands fail
+    #[serde(rename = "lockedDown")]
+    pub locked_down: Option<bool>,
+    /// Set by the migration API's fee lock, see `locked_down`
+    #[serde(rename = "feeLocked")]
+    pub fee_locked: Option<bool>,
 }
 
 impl Merchant {
@@ -74,6 +81,8 @@ impl Merchant {
             balance: Some(1000.50),
             credit_limit: Some(5000.00),
             advertiser_platform_plan: "premium".to_string(),
+            locked_down: Some(false),
+            fee_locked: Some(false),
         }
     }
 }
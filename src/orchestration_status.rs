@@ -1,46 +1,3 @@
-use serde::{Deserialize, Serialize};
-use std::fmt;
-
-impl std::error::Error for OrchestratorError {}
-/// Represents the status response from the orchestrator API
-#[derive(Debug, Serialize, Deserialize)]
-pub struct OrchestatorStatusReturn {
-    pub timestamp: String,
-    pub status: i16,
-    pub error: String,
-    pub path: String,
-}
-
-/// Possible errors that can occur during orchestrator operations
-#[derive(Debug)]
-pub enum OrchestratorError {
-    InvalidAdvertiserId,
-    RequestError(reqwest::Error),
-    ParseError(serde_json::Error),
-    NotFound(OrchestatorStatusReturn),
-    Unauthorized,
-    UnexpectedStatus(reqwest::StatusCode),
-}
-
-/// Implementation for mapping error enums to an error string
-impl fmt::Display for OrchestratorError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            OrchestratorError::InvalidAdvertiserId => {
-                write!(f, "Advertiser ID must be greater than 0")
-            }
-            OrchestratorError::RequestError(e) => write!(f, "Request error: {}", e),
-            OrchestratorError::ParseError(e) => write!(f, "Failed to parse response: {}", e),
-            OrchestratorError::NotFound(status) => write!(f, "Advertiser not found: {:?}", status),
-            OrchestratorError::Unauthorized => write!(f, "Unauthorized access: Invalid JWT token"),
-            OrchestratorError::UnexpectedStatus(status) => {
-                write!(f, "Unexpected status code: {}", status)
-            }
-        }
-    }
-}
-
-
 /// Mirror the growth-account-migration-service enum values
 #[derive(Debug, Clone, PartialEq)]
 pub enum MigrationStatus {
@@ -122,6 +79,87 @@ pub enum StepDescriptor {
 }
 
 impl StepDescriptor {
+    /// Every step, in the order the orchestrator runs them
+    pub const ALL: [StepDescriptor; 12] = [
+        StepDescriptor::Init,
+        StepDescriptor::Valid,
+        StepDescriptor::Sf,
+        StepDescriptor::Adv,
+        StepDescriptor::Pub,
+        StepDescriptor::Track,
+        StepDescriptor::Vouch,
+        StepDescriptor::MemTag,
+        StepDescriptor::Com,
+        StepDescriptor::Fee,
+        StepDescriptor::Feed,
+        StepDescriptor::Creative,
+    ];
+
+    /// Name of the step in the growth-account-migration-service API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StepDescriptor::Init => "INIT",
+            StepDescriptor::Valid => "VALID",
+            StepDescriptor::Sf => "SF",
+            StepDescriptor::Adv => "ADV",
+            StepDescriptor::Pub => "PUB",
+            StepDescriptor::Track => "TRACK",
+            StepDescriptor::Vouch => "VOUCH",
+            StepDescriptor::MemTag => "MEM_TAG",
+            StepDescriptor::Com => "COM",
+            StepDescriptor::Fee => "FEE",
+            StepDescriptor::Feed => "FEED",
+            StepDescriptor::Creative => "CREATIVE",
+        }
+    }
+
+    /// Parses a comma separated list of steps and inclusive ranges, e.g.
+    /// `VALID`, `SF,ADV` or `VALID..FEE`, keeping the order they were given in
+    pub fn parse_list(spec: &str) -> Result<Vec<Self>, String> {
+        let mut steps = Vec::new();
+        for part in spec.split(',').map(str::trim) {
+            if part.is_empty() {
+                return Err(format!("Empty step in '{}'", spec));
+            }
+            match part.split_once("..") {
+                Some((first, last)) => {
+                    let first = Self::parse(first.trim())?.position();
+                    let last = Self::parse(last.trim())?.position();
+                    if first > last {
+                        return Err(format!(
+                            "Step range {} is backwards, steps run in the order {}",
+                            part,
+                            Self::names()
+                        ));
+                    }
+                    steps.extend_from_slice(&Self::ALL[first..=last]);
+                }
+                None => steps.push(Self::parse(part)?),
+            }
+        }
+        Ok(steps)
+    }
+
+    fn parse(name: &str) -> Result<Self, String> {
+        Self::from_string(name)
+            .ok_or_else(|| format!("Unknown step {}, expected one of {}", name, Self::names()))
+    }
+
+    fn position(&self) -> usize {
+        Self::ALL
+            .iter()
+            .position(|step| step == self)
+            .expect("every step is listed in ALL")
+    }
+
+    fn names() -> String {
+        Self::ALL
+            .iter()
+            .map(StepDescriptor::as_str)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     pub fn from_string(s: &str) -> Option<Self> {
         match s.to_uppercase().as_str() {
             "INIT" => Some(StepDescriptor::Init),
@@ -208,3 +246,40 @@ impl MigrationStatus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_lists_and_ranges_keep_their_order() {
+        assert_eq!(
+            StepDescriptor::parse_list("valid").unwrap(),
+            vec![StepDescriptor::Valid]
+        );
+        assert_eq!(
+            StepDescriptor::parse_list("COM, INIT").unwrap(),
+            vec![StepDescriptor::Com, StepDescriptor::Init]
+        );
+        assert_eq!(
+            StepDescriptor::parse_list("INIT,PUB..VOUCH").unwrap(),
+            vec![
+                StepDescriptor::Init,
+                StepDescriptor::Pub,
+                StepDescriptor::Track,
+                StepDescriptor::Vouch,
+            ]
+        );
+        assert_eq!(StepDescriptor::parse_list("VALID..FEE").unwrap().len(), 9);
+    }
+
+    #[test]
+    fn unknown_steps_and_backwards_ranges_are_rejected() {
+        let error = StepDescriptor::parse_list("VALID,MEMTAG").unwrap_err();
+        assert!(error.contains("Unknown step MEMTAG"), "{}", error);
+        assert!(error.contains("MEM_TAG"), "{}", error);
+        assert!(StepDescriptor::parse_list("FEE..VALID").is_err());
+        assert!(StepDescriptor::parse_list("VALID,").is_err());
+        assert!(StepDescriptor::parse_list("").is_err());
+    }
+}
@@ -1,240 +1,262 @@
 use crate::{
-    advertiser,
-    config::TestConfig,
-    orchestration_status::*,
-    request::growth_migration_post,
+    advertiser, config::TestConfig, error::TigerClawError, growth_migration::GrowthMigrationClient,
+    orchestration_status::StepDescriptor, retry::RetryPolicy, setup::Environment,
 };
+use log::{error, info, warn};
 
 /// Executes the orchestrator workflow for a given advertiser
 ///
 /// # Arguments
-/// * `jwt_token` - JWT token for authentication
-/// * `test_config` - The test configuration
-//
+/// * `client` - Client for growth-account-migration-service
+/// * `test_config` - The test configuration, `orchestration.step_to_run` may
+///   list several steps or a range, see `StepDescriptor::parse_list`
+/// * `retry` - The run's retry policy, used for the DynamoDB lookups
+/// * `environment` - Picks the AWS profile of the DynamoDB lookups, see
+///   `aws::profile`
+///
 /// # Returns
-/// This function returns nothing but may print error messages to stderr
-pub async fn run(auth_token: &str, test_config: &TestConfig) {
-    if test_config.globals.external_id.is_none() {
-        eprintln!("No external ID provided");
-        return;
-    }
-
-    let external_id = test_config.globals.external_id.unwrap();
+/// The steps to run are still attempted if initializing or forcing the status
+/// failed, but stop at the first step that fails. The first failure is returned
+pub async fn run(
+    client: &GrowthMigrationClient,
+    test_config: &TestConfig,
+    retry: &RetryPolicy,
+    environment: Environment,
+) -> Result<(), TigerClawError> {
+    let external_id = match test_config.globals.external_id {
+        Some(external_id) => external_id,
+        None => {
+            return Err(TigerClawError::Config(
+                "No external ID provided".to_string(),
+            ));
+        }
+    };
 
     // Validate we have an external_id > 0
     if external_id <= 0 {
-        eprintln!("Invalid external_id: {}", external_id);
-        return;
+        return Err(TigerClawError::Config(format!(
+            "Invalid external_id: {}",
+            external_id
+        )));
     }
 
+    let steps = StepDescriptor::parse_list(&test_config.orchestration.step_to_run)
+        .map_err(TigerClawError::Config)?;
+
+    let mut outcome = Ok(());
+
     // Check if the current advertiser already exists, if it doesn't then
     // initialize it
-    if advertiser::get_advertiser_by_external_id(external_id)
-        .await
-        .is_err()
-    {
-        eprintln!("Advertiser with external ID {} not found or errored, re-initializing", external_id);
-        match execute_step(auth_token, &external_id, "INIT", test_config).await {
+    let mut before = snapshot(external_id, retry, environment, client.dry_run()).await?;
+    if before.is_none() {
+        warn!(
+            "Advertiser with external ID {} not found, re-initializing",
+            external_id
+        );
+        match client.execute_step(external_id, "INIT").await {
             Ok(_) => {
-                println!("Advertiser {} initialized successfully", external_id);
+                info!("Advertiser {} initialized successfully", external_id);
+            }
+            Err(error) if error.is_timeout() => {
+                error!(
+                    "TIMED OUT: initializing merchant ID {}: {}",
+                    external_id, error
+                );
+                outcome = outcome.and(Err(error));
             }
             Err(error) => {
-                eprintln!("Failed to initialize merchant ID {}: {}", external_id, error);
+                error!(
+                    "Failed to initialize merchant ID {}: {}",
+                    external_id, error
+                );
+                outcome = outcome.and(Err(error));
             }
         }
+        let after = recorded(
+            snapshot(external_id, retry, environment, client.dry_run()).await,
+            &mut outcome,
+        );
+        advertiser::print_diff("INIT", before.as_ref(), after.as_ref());
+        before = after;
     }
 
     // if force_set_status is set, force the update to the advertiser
     if test_config.orchestration.force_run {
-        println!("Force running migration for advertiser {}", external_id);
-        match force_update_status(auth_token, test_config).await {
-            Ok(response) => {
-                println!(
-                    "Successfully forced status update for merchant ID {}",
-                    external_id
+        info!("Force running migration for advertiser {}", external_id);
+        match client
+            .force_status(external_id, &test_config.orchestration.step_status_to_force)
+            .await
+        {
+            Ok(_) => {
+                info!(
+                    "Successfully forced status update to '{}' for merchant ID {}",
+                    test_config.orchestration.step_status_to_force, external_id
                 );
-                if response.status().is_success() {
-                    println!(
-                        "SAS migration started successfully for merchant_id: {}",
-                        external_id
-                    );
-                } else {
-                    eprintln!(
-                        "Failed to force status update for merchant ID {}. Status: {}",
-                        external_id,
-                        response.status()
-                    );
-                }
+            }
+            Err(error) if error.is_timeout() => {
+                error!(
+                    "TIMED OUT: forcing status update for merchant ID {}: {}",
+                    external_id, error
+                );
+                outcome = outcome.and(Err(error));
             }
             Err(error) => {
-                eprintln!(
+                error!(
                     "Failed to force status update for merchant ID {}: {}",
                     external_id, error
                 );
+                outcome = outcome.and(Err(error));
             }
         }
+        let after = recorded(
+            snapshot(external_id, retry, environment, client.dry_run()).await,
+            &mut outcome,
+        );
+        let label = format!("force {}", test_config.orchestration.step_status_to_force);
+        advertiser::print_diff(&label, before.as_ref(), after.as_ref());
+        before = after;
     }
 
-    println!(
-        "Running orchestration step{} for advertiser {}",
-        test_config.orchestration.step_to_run, external_id
-    );
-
-    // attempt to run the step last
-    match execute_step(
-        auth_token,
-        &external_id,
-        &test_config.orchestration.step_to_run,
-        test_config,
-    )
-    .await
-    {
-        Ok(response) => {
-            if response.status().is_success() {
-                println!(
-                    "SAS migration started successfully for merchant_id: {}",
+    // run the steps last, in order. A step builds on the one before it, so
+    // the rest are skipped once one fails
+    for step in steps {
+        info!(
+            "Running orchestration step {} for advertiser {}",
+            step.as_str(),
+            external_id
+        );
+        let result = client.execute_step(external_id, step.as_str()).await;
+        match &result {
+            Ok(_) => {
+                info!(
+                    "SAS migration step {} started successfully for merchant_id: {}",
+                    step.as_str(),
                     external_id
                 );
-            } else {
-                eprintln!(
-                    "Failed to execute step for merchant ID {}. Status: {} response:{:?}",
-                    external_id,
-                    response.status(),
-                    response.text().await
+            }
+            Err(e) if e.is_timeout() => {
+                error!(
+                    "TIMED OUT: executing step {}, it may still be running: {}",
+                    step.as_str(),
+                    e
                 );
             }
+            Err(e) => {
+                error!("Failed to execute step {}: {}", step.as_str(), e);
+            }
         }
-        Err(e) => {
-            eprintln!("Failed to execute step: {:?}", e);
+        let after = recorded(
+            snapshot(external_id, retry, environment, client.dry_run()).await,
+            &mut outcome,
+        );
+        advertiser::print_diff(step.as_str(), before.as_ref(), after.as_ref());
+        before = after;
+        if let Err(e) = result {
+            outcome = outcome.and(Err(e));
+            break;
         }
     }
+    outcome
 }
 
-/// Executes a specific step in the migration process
-///
-/// # Arguments
-/// * `jwt_token` - JWT token for authentication
-/// * `external_id` - The ID of the SAS merchant ID
-/// * `step` - The step to execute
-/// * `test_config` - The test configuration
-///
-/// # Returns
-/// Returns `Ok(OrchestatorStatusReturn)` on success, or an `OrchestratorError` on failure
-pub async fn execute_step(
-    jwt_token: &str,
-    external_id: &i32,
-    step: &str,
-    test_config: &TestConfig,
-) -> Result<reqwest::Response, OrchestratorError> {
-    let endpoint = format!(
-        "migrate/sas/advertiser/{}/execute-step/{}",
-        external_id, step
-    );
-
-    match growth_migration_post(&endpoint, jwt_token.to_string(), test_config).await {
-        Ok(response) => match response.status() {
-            reqwest::StatusCode::OK => Ok(response),
-            reqwest::StatusCode::NOT_FOUND => {
-                let status = response
-                    .json::<OrchestatorStatusReturn>()
-                    .await
-                    .map_err(OrchestratorError::RequestError)?;
-                Err(OrchestratorError::NotFound(status))
-            }
-            reqwest::StatusCode::UNAUTHORIZED => Err(OrchestratorError::Unauthorized),
-            status => Err(OrchestratorError::UnexpectedStatus(status)),
-        },
-        Err(e) => Err(OrchestratorError::RequestError(e)),
+/// Reads the current advertiser record from DynamoDB so it can be diffed
+/// against the record after a step has run. `None` when there is no record
+/// yet, DynamoDB errors are returned
+async fn snapshot(
+    external_id: i32,
+    retry: &RetryPolicy,
+    environment: Environment,
+    dry_run: bool,
+) -> Result<Option<advertiser::Advertiser>, TigerClawError> {
+    if dry_run {
+        info!(
+            "Dry run, sending {}",
+            advertiser::describe_lookup(external_id)
+        );
     }
+    advertiser::find_advertiser_by_external_id(external_id, retry, environment).await
 }
 
-/// Force update the migration status for an advertiser
-/// # Arguments
-/// * `jwt_token` - JWT token for authentication
-/// * `test_config` - The test configuration
-/// # Returns
-/// Returns `Ok(())` on success, or an `OrchestratorError` on failure
-pub async fn force_update_status(
-    jwt_token: &str,
-    test_config: &TestConfig,
-) -> Result<reqwest::Response, OrchestratorError> {
-    let external_id = test_config.globals.external_id.unwrap();
-    let endpoint = format!("migrate/sas/advertiser/{}/status", external_id);
-
-    // Prepare request body
-    let request_body = serde_json::json!({
-        "force_status": test_config.orchestration.step_status_to_force
-    });
-
-    // Create client
-    let client = reqwest::Client::new();
-
-    // Build the request
-    let request = client
-        .patch(format!(
-            "{}/{}",
-            test_config.globals.base_growth_migration_url, endpoint
-        ))
-        .header("Authorization", format!("Bearer {}", jwt_token))
-        .header("Content-Type", "application/json")
-        .json(&request_body);
-
-    // Send the request
-    match request.send().await {
-        Ok(response) => match response.status() {
-            reqwest::StatusCode::OK
-            | reqwest::StatusCode::ACCEPTED
-            | reqwest::StatusCode::NO_CONTENT => {
-                println!(
-                    "Successfully forced status update to '{}' for merchant ID {:?}",
-                    test_config.orchestration.step_status_to_force,
-                    test_config.globals.external_id
-                );
-                Ok(response)
+/// The record a snapshot read, or `None` when it couldn't be read. The error
+/// is logged and kept in `outcome` so the run still fails
+fn recorded(
+    snapshot: Result<Option<advertiser::Advertiser>, TigerClawError>,
+    outcome: &mut Result<(), TigerClawError>,
+) -> Option<advertiser::Advertiser> {
+    match snapshot {
+        Ok(advertiser) => advertiser,
+        Err(error) => {
+            error!("Failed to read the advertiser record: {}", error);
+            if outcome.is_ok() {
+                *outcome = Err(error);
             }
-            reqwest::StatusCode::UNAUTHORIZED => Err(OrchestratorError::Unauthorized),
-            reqwest::StatusCode::NOT_FOUND => {
-                let status = response
-                    .json::<OrchestatorStatusReturn>()
-                    .await
-                    .map_err(OrchestratorError::RequestError)?;
-                Err(OrchestratorError::NotFound(status))
-            }
-            status => {
-                println!("Unexpected status code: {}", status);
-                Err(OrchestratorError::UnexpectedStatus(status))
-            }
-        },
-        Err(e) => Err(OrchestratorError::RequestError(e)),
+            None
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::config::load_test_config;
+    use super::recorded;
+    use crate::auth::ClientCredentials;
+    use crate::config::{AuthConfig, load_test_config};
+    use crate::error::TigerClawError;
+    use crate::growth_migration::GrowthMigrationClient;
+    use crate::logger::LogFormat;
     use crate::setup::{self};
+    use std::sync::Arc;
 
-    /// Helper function to get the environment and token for tests
-    async fn get_environment_and_token() -> (String, setup::Environment) {
+    /// Helper function to build a growth migration client for the local config
+    async fn get_client() -> GrowthMigrationClient {
         let args = setup::Args {
             verbose: true,
-            environmnet: "dev".to_string(),
+            quiet: false,
+            log_format: LogFormat::Text,
+            environmnet: Some("dev".to_string()),
             advertiser_id: Some(424242),
             external_id: Some(242424),
             migration_step: None,
             toml_config: None,
+            read_only: false,
+            allow_mutations: false,
+            dry_run: false,
+            command: None,
         };
 
-        setup::get_token_and_environment(&args).await
+        let (tokens, _environment) =
+            setup::get_token_and_environment(&args, &AuthConfig::default()).unwrap();
+        let config = load_test_config("tests.local.toml").unwrap();
+        GrowthMigrationClient::new(
+            &config.globals,
+            Arc::new(ClientCredentials::new(Arc::new(tokens))),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn snapshot_errors_fail_the_run_but_keep_the_first_failure() {
+        let mut outcome = Ok(());
+        let lookup = Err(TigerClawError::Config("no credentials".to_string()));
+        assert!(recorded(lookup, &mut outcome).is_none());
+        assert!(matches!(outcome, Err(TigerClawError::Config(_))));
+
+        let lookup = Err(TigerClawError::Assertion("later".to_string()));
+        recorded(lookup, &mut outcome);
+        assert!(matches!(outcome, Err(TigerClawError::Config(_))));
+
+        let mut outcome = Ok(());
+        assert!(recorded(Ok(None), &mut outcome).is_none());
+        assert!(outcome.is_ok());
     }
 
     #[tokio::test]
     async fn force_update_advertiser() {
-        let (token, _environment) = get_environment_and_token().await;
-        let mut config = load_test_config("tests.local.toml").unwrap();
-        config.orchestration.step_status_to_force = "COMPLETED".to_string();
-        super::force_update_status(&token, &config).await.unwrap();
+        let client = get_client().await;
+        let config = load_test_config("tests.local.toml").unwrap();
+        client
+            .force_status(config.globals.external_id.unwrap(), "COMPLETED")
+            .await
+            .unwrap();
     }
 
     /// This test will iterate through each step in the StepDescriptor enum
@@ -243,7 +265,7 @@ mod tests {
     #[tokio::test]
     #[ignore]
     async fn test_execute_all_steps() {
-        let (token, _environment) = get_environment_and_token().await;
+        let client = get_client().await;
 
         // Set up test data
         let advertiser_id = 424242;
@@ -254,13 +276,11 @@ mod tests {
             "CREATIVE",
         ];
 
-        let config = load_test_config("tests.local.toml").unwrap();
-
         for step in steps {
             println!("Testing step: {}", step);
 
             // Execute the step
-            match execute_step(&token, &advertiser_id, step, &config).await {
+            match client.execute_step(advertiser_id, step).await {
                 Ok(response) => {
                     let status = response.status();
                     println!("  Success! Status: {:?}", status);
@@ -277,24 +297,24 @@ mod tests {
         }
     }
 
-    /// Test the force_update_status function
+    /// Test the force_status call
     #[tokio::test]
     #[ignore]
     async fn test_force_update_status() {
-        let (token, _environment) = get_environment_and_token().await;
+        let client = get_client().await;
 
         // Set up test data
         // Test the force update function with various statuses
         let statuses = vec!["INIT_DONE", "VALID_DONE", "COMPLETED"];
 
         // load local toml config
-        let mut config = load_test_config("tests.local.toml").unwrap();
+        let config = load_test_config("tests.local.toml").unwrap();
+        let external_id = config.globals.external_id.unwrap();
 
         for status in statuses {
             println!("Testing force update to status: {}", status);
-            config.orchestration.step_status_to_force = status.to_string();
 
-            match force_update_status(&token, &config).await {
+            match client.force_status(external_id, status).await {
                 Ok(_) => {
                     println!("  Successfully forced status to {}", status);
                 }
@@ -1,5 +1,10 @@
-use aws_sdk_dynamodb::{Client, Error, types::AttributeValue};
+use crate::aws;
+use crate::error::TigerClawError;
+use crate::retry::{Idempotency, RetryPolicy, should_retry_dynamo};
+use crate::setup::Environment;
+use aws_sdk_dynamodb::types::AttributeValue;
 use chrono;
+use log::debug;
 
 const PUBLISHER_TABLE_NAME: &str = "external-pub-awin-migration";
 
@@ -13,22 +18,33 @@ pub struct Publisher {
     pub start_date: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-pub async fn get_publisher_by_external_id(external_id: i32) -> Result<Publisher, Error> {
-    let config = aws_config::from_env()
-        .profile_name("org-adm-springfield-dev-poweruser")
-        .load()
-        .await;
-    let client = Client::new(&config);
+pub async fn get_publisher_by_external_id(
+    external_id: i32,
+    retry: &RetryPolicy,
+    environment: Environment,
+) -> Result<Publisher, TigerClawError> {
+    let client = aws::dynamo_db_client(environment).await?;
 
-    println!("Getting publisher from DynamoDB");
-    let output = client
-        .scan()
-        .table_name(PUBLISHER_TABLE_NAME)
-        .filter_expression("external_id = :external_id")
-        .expression_attribute_values(":external_id", AttributeValue::S(external_id.to_string()))
-        .send()
+    debug!("Getting publisher from DynamoDB");
+    let output = retry
+        .run(
+            "Publisher lookup",
+            Idempotency::Idempotent,
+            should_retry_dynamo,
+            || {
+                client
+                    .scan()
+                    .table_name(PUBLISHER_TABLE_NAME)
+                    .filter_expression("external_id = :external_id")
+                    .expression_attribute_values(
+                        ":external_id",
+                        AttributeValue::S(external_id.to_string()),
+                    )
+                    .send()
+            },
+        )
         .await?;
-    println!("Output: {:?}", output);
+    debug!("Output: {:?}", output);
 
     //TODO: Have to fix this to return the actual publisher instead of hard
     //coded values
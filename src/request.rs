@@ -1,80 +1,233 @@
-use crate::config::TestConfig;
-use reqwest::Response;
-
-pub async fn growth_migration_get(
-    endpoint_path: &str,
-    jwt_token: &str,
-    test_config: &TestConfig,
-) -> Result<Response, reqwest::Error> {
-    let url = format!(
-        "{}:{}/{}",
-        test_config.globals.base_growth_migration_url,
-        test_config.globals.base_growth_migration_port,
-        endpoint_path
-    );
-    let client = reqwest::Client::new();
-    client
-        .get(&url)
-        .header("Authorization", format!("Bearer {}", jwt_token))
-        .send()
-        .await
+use crate::auth::AuthProvider;
+use crate::error::TigerClawError;
+use crate::retry::{Idempotency, RetryPolicy};
+use log::{Level, debug, info, log_enabled, warn};
+use reqwest::header::HeaderMap;
+use reqwest::{Request, RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use std::time::{Duration, Instant};
+
+/// Headers whose values are never printed, whatever they contain
+const SENSITIVE_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+/// A response whose body has already been read, so the exchange can be logged
+/// and the body still decoded afterwards
+#[derive(Debug)]
+pub struct BufferedResponse {
+    url: Url,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
 }
 
-pub async fn growth_migration_post(
-    endpoint_path: &str,
-    jwt_token: String,
-    test_config: &TestConfig,
-) -> Result<Response, reqwest::Error> {
-    let url = format!(
-        "{}:{}/{}",
-        test_config.globals.base_growth_migration_url,
-        test_config.globals.base_growth_migration_port,
-        endpoint_path
-    );
-    let client = reqwest::Client::new();
-    client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", jwt_token))
-        .send()
-        .await
+impl BufferedResponse {
+    /// Stand-in for a mutating request a dry run didn't send, a 204 without
+    /// headers or body
+    pub fn not_sent(url: Url) -> Self {
+        BufferedResponse {
+            url,
+            status: StatusCode::NO_CONTENT,
+            headers: HeaderMap::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, TigerClawError> {
+        serde_json::from_str(&self.body).map_err(|e| TigerClawError::Decode {
+            url: self.url.to_string(),
+            message: e.to_string(),
+        })
+    }
 }
 
-pub async fn sas_data_import_get(
-    endpoint_path: &str,
-    jwt_token: &str,
-    config: &TestConfig,
-) -> Result<Response, reqwest::Error> {
-    let url = format!(
-        "{}/{}",
-        config.globals.base_sas_data_import_url, endpoint_path
-    );
-    let client = reqwest::Client::new();
-    client
-        .get(&url)
-        .header("Authorization", format!("Bearer {}", jwt_token))
-        .send()
-        .await
+/// Sends the request and reads the whole body. At debug level every exchange is
+/// logged with headers, body and latency, otherwise only the body of a failed
+/// response is, as a warning
+pub async fn execute(request: RequestBuilder) -> Result<BufferedResponse, reqwest::Error> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let verbose = log_enabled!(Level::Debug);
+    if verbose {
+        debug!("{}", format_request(&request));
+    }
+
+    let started = Instant::now();
+    let response = client.execute(request).await?;
+    let url = response.url().clone();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.text().await?;
+    let response = BufferedResponse {
+        url,
+        status,
+        headers,
+        body,
+    };
+
+    if verbose {
+        debug!("{}", format_response(&response, Some(started.elapsed())));
+    } else if !status.is_success() {
+        warn!("{}", format_response(&response, None));
+    }
+    Ok(response)
+}
+
+/// Sends a request with credentials from `auth`. If the service rejects them
+/// with a 401 and the provider can hand out new ones, the request is sent
+/// exactly once more
+pub async fn send_authenticated<F>(
+    auth: &dyn AuthProvider,
+    build_request: F,
+) -> Result<BufferedResponse, reqwest::Error>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let response = execute(auth.authorize(build_request()).await).await?;
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(response);
+    }
+
+    if !auth.invalidate().await {
+        return Ok(response);
+    }
+    info!("Request was unauthorized, refreshing credentials and retrying");
+    execute(auth.authorize(build_request()).await).await
 }
 
-pub async fn sas_data_import_put(
-    endpoint_path: &str,
-    jwt_token: String,
-    test_config: &TestConfig,
-) -> Response {
-    let url = format!(
-        "{}/{}",
-        test_config.globals.base_sas_data_import_url, endpoint_path
-    );
-    let client = reqwest::Client::new();
-    match client
-        .put(&url)
-        .header("Authorization", format!("Bearer {}", jwt_token))
-        .send()
+/// `send_authenticated` wrapped in the retry policy, so gateway errors and
+/// dropped connections are retried with backoff. Non-idempotent requests are
+/// sent once unless the policy allows retrying them
+pub async fn send_with_retry<F>(
+    auth: &dyn AuthProvider,
+    retry: &RetryPolicy,
+    idempotency: Idempotency,
+    build_request: F,
+) -> Result<BufferedResponse, reqwest::Error>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let description = describe(&build_request);
+    retry
+        .run(
+            &description,
+            idempotency,
+            |result| retry.should_retry_http(result),
+            || send_authenticated(auth, &build_request),
+        )
         .await
+}
+
+/// Method and URL of the request for retry and dry run messages
+pub(crate) fn describe<F: Fn() -> RequestBuilder>(build_request: &F) -> String {
+    match build_request().build() {
+        Ok(request) => format!("{} {}", request.method(), request.url()),
+        Err(_) => "Request".to_string(),
+    }
+}
+
+fn format_request(request: &Request) -> String {
+    let mut lines = vec![format!("--> {} {}", request.method(), request.url())];
+    lines.extend(format_headers(request.headers()));
+    if let Some(body) = request.body().and_then(|body| body.as_bytes())
+        && !body.is_empty()
     {
-        Ok(response) => response,
-        Err(error) => {
-            panic!("put request error: {:?}", error);
-        }
+        lines.push(format!("    {}", String::from_utf8_lossy(body)));
+    }
+    lines.join("\n")
+}
+
+fn format_response(response: &BufferedResponse, latency: Option<Duration>) -> String {
+    let mut first_line = format!("<-- {} {}", response.status, response.url);
+    if let Some(latency) = latency {
+        first_line.push_str(&format!(" ({}ms)", latency.as_millis()));
+    }
+    let mut lines = vec![first_line];
+    lines.extend(format_headers(&response.headers));
+    if !response.body.is_empty() {
+        lines.push(format!("    {}", response.body));
+    }
+    lines.join("\n")
+}
+
+fn format_headers(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+                "****".to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).to_string()
+            };
+            format!("    {}: {}", name, value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_headers_are_redacted_and_body_kept() {
+        let request = reqwest::Client::new()
+            .patch("http://localhost/migrate/sas/advertiser/1/status")
+            .header("Authorization", "Bearer not-a-real-token")
+            .header("X-Trace", "abc")
+            .json(&serde_json::json!({ "force_status": "COMPLETED" }))
+            .build()
+            .unwrap();
+
+        let formatted = format_request(&request);
+        assert!(
+            formatted.starts_with("--> PATCH http://localhost/migrate/sas/advertiser/1/status")
+        );
+        assert!(formatted.contains("authorization: ****"));
+        assert!(formatted.contains("x-trace: abc"));
+        assert!(formatted.contains(r#"{"force_status":"COMPLETED"}"#));
+        assert!(!formatted.contains("not-a-real-token"));
+    }
+
+    #[tokio::test]
+    async fn response_body_is_kept_after_logging() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/merchant/1")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"merchantId":1}"#)
+            .create_async()
+            .await;
+
+        let response = execute(reqwest::Client::new().get(format!("{}/merchant/1", server.url())))
+            .await
+            .unwrap();
+        let formatted = format_response(&response, Some(Duration::from_millis(12)));
+        assert!(formatted.starts_with("<-- 200 OK "));
+        assert!(formatted.contains("(12ms)"));
+        assert!(formatted.contains("content-type: application/json"));
+        let body: serde_json::Value = response.json().unwrap();
+        assert_eq!(body["merchantId"], 1);
     }
 }
@@ -0,0 +1,322 @@
+use crate::request::BufferedResponse;
+use aws_sdk_dynamodb::config::http::HttpResponse;
+use aws_sdk_dynamodb::error::{ProvideErrorMetadata, SdkError};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Retry settings from the optional `[retry]` config section
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Total attempts including the first one, 1 disables retries
+    pub max_attempts: u32,
+    /// Backoff before the first retry, doubled for every retry after it
+    pub initial_backoff_ms: u64,
+    /// Upper bound for a single backoff
+    pub max_backoff_ms: u64,
+    /// HTTP statuses worth retrying, anything else is returned as is
+    pub retryable_statuses: Vec<u16>,
+    /// Also retry calls that aren't safe to repeat, such as execute-step
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 200,
+            max_backoff_ms: 5000,
+            retryable_statuses: vec![502, 503, 504],
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+/// Whether repeating a call could change the outcome. Only idempotent calls are
+/// retried unless `retry_non_idempotent` is set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Idempotency {
+    Idempotent,
+    NonIdempotent,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    config: RetryConfig,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(&RetryConfig::default())
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(config: &RetryConfig) -> Self {
+        RetryPolicy {
+            config: config.clone(),
+        }
+    }
+
+    /// A policy that makes exactly one attempt
+    pub fn none() -> Self {
+        RetryPolicy::new(&RetryConfig {
+            max_attempts: 1,
+            ..RetryConfig::default()
+        })
+    }
+
+    fn max_attempts(&self, idempotency: Idempotency) -> u32 {
+        if idempotency == Idempotency::NonIdempotent && !self.config.retry_non_idempotent {
+            return 1;
+        }
+        self.config.max_attempts.max(1)
+    }
+
+    pub fn is_retryable_status(&self, status: reqwest::StatusCode) -> bool {
+        self.config.retryable_statuses.contains(&status.as_u16())
+    }
+
+    /// Exponential backoff with equal jitter: half the backoff is fixed and the
+    /// other half random, so parallel runs don't retry in lockstep
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .config
+            .initial_backoff_ms
+            .saturating_mul(1u64 << retry.min(16))
+            .min(self.config.max_backoff_ms);
+        let half = exponential / 2;
+        Duration::from_millis(half + jitter(exponential - half))
+    }
+
+    /// Runs `operation` until it succeeds, `should_retry` says the result isn't
+    /// worth retrying, or the attempts run out. The last result is returned
+    pub async fn run<T, F, Fut>(
+        &self,
+        description: &str,
+        idempotency: Idempotency,
+        should_retry: impl Fn(&T) -> bool,
+        mut operation: F,
+    ) -> T
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let max_attempts = self.max_attempts(idempotency);
+        let mut attempt = 1;
+        loop {
+            let result = operation().await;
+            if attempt >= max_attempts || !should_retry(&result) {
+                return result;
+            }
+            let backoff = self.backoff(attempt - 1);
+            warn!(
+                "{} failed with a transient error, retrying in {}ms (attempt {}/{})",
+                description,
+                backoff.as_millis(),
+                attempt + 1,
+                max_attempts
+            );
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    /// Retry classification for HTTP calls: configured statuses, connection
+    /// failures, timeouts and connection resets
+    pub fn should_retry_http(&self, result: &Result<BufferedResponse, reqwest::Error>) -> bool {
+        match result {
+            Ok(response) => self.is_retryable_status(response.status()),
+            Err(error) => error.is_connect() || error.is_timeout() || is_connection_reset(error),
+        }
+    }
+}
+
+/// True if anything in the error chain is an io error caused by the other end
+/// or a port-forward dropping the connection
+pub fn is_connection_reset(error: &(dyn StdError + 'static)) -> bool {
+    let mut source: Option<&(dyn StdError + 'static)> = Some(error);
+    while let Some(current) = source {
+        if let Some(io_error) = current.downcast_ref::<std::io::Error>()
+            && matches!(
+                io_error.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+            )
+        {
+            return true;
+        }
+        source = current.source();
+    }
+    false
+}
+
+/// Retry classification for DynamoDB calls: dispatch failures, timeouts,
+/// unreadable responses, server errors and throttling
+pub fn should_retry_dynamo<T, E: ProvideErrorMetadata>(
+    result: &Result<T, SdkError<E, HttpResponse>>,
+) -> bool {
+    match result {
+        Ok(_) => false,
+        Err(
+            SdkError::DispatchFailure(_) | SdkError::TimeoutError(_) | SdkError::ResponseError(_),
+        ) => true,
+        Err(SdkError::ServiceError(context)) => {
+            context.raw().status().is_server_error()
+                || matches!(
+                    context.err().code(),
+                    Some(
+                        "ThrottlingException"
+                            | "ProvisionedThroughputExceededException"
+                            | "RequestLimitExceeded"
+                    )
+                )
+        }
+        Err(_) => false,
+    }
+}
+
+/// Random value in `0..=max` without pulling in a rand dependency, each
+/// `RandomState` is seeded randomly by std
+fn jitter(max: u64) -> u64 {
+    if max == 0 {
+        return 0;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    random % (max + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::execute;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_policy(retry_non_idempotent: bool) -> RetryPolicy {
+        RetryPolicy::new(&RetryConfig {
+            max_attempts: 3,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 2,
+            retry_non_idempotent,
+            ..RetryConfig::default()
+        })
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let policy = RetryPolicy::new(&RetryConfig {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+            ..RetryConfig::default()
+        });
+        for _ in 0..20 {
+            let first = policy.backoff(0).as_millis();
+            assert!((50..=100).contains(&first), "first backoff {}", first);
+            let capped = policy.backoff(10).as_millis();
+            assert!((500..=1000).contains(&capped), "capped backoff {}", capped);
+        }
+    }
+
+    #[tokio::test]
+    async fn idempotent_calls_retry_until_attempts_run_out() {
+        let attempts = AtomicU32::new(0);
+        let result = fast_policy(false)
+            .run(
+                "test",
+                Idempotency::Idempotent,
+                |r: &Result<(), ()>| r.is_err(),
+                || async {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    Err::<(), ()>(())
+                },
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn non_idempotent_calls_only_retry_when_allowed() {
+        for (allowed, expected) in [(false, 1), (true, 3)] {
+            let attempts = AtomicU32::new(0);
+            fast_policy(allowed)
+                .run(
+                    "test",
+                    Idempotency::NonIdempotent,
+                    |_: &()| true,
+                    || async {
+                        attempts.fetch_add(1, Ordering::SeqCst);
+                    },
+                )
+                .await;
+            assert_eq!(attempts.load(Ordering::SeqCst), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn gateway_errors_are_retried() {
+        let mut server = mockito::Server::new_async().await;
+        // The first matching mock with hits left answers, so one 503 then 200s
+        let unavailable = server
+            .mock("GET", "/flaky")
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        let recovered = server
+            .mock("GET", "/flaky")
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        let policy = fast_policy(false);
+        let client = reqwest::Client::new();
+        let url = format!("{}/flaky", server.url());
+
+        let response = policy
+            .run(
+                "GET /flaky",
+                Idempotency::Idempotent,
+                |r| policy.should_retry_http(r),
+                || execute(client.get(&url)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        unavailable.assert_async().await;
+        recovered.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_returned_immediately() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/broken")
+            .with_status(500)
+            .expect(1)
+            .create_async()
+            .await;
+        let policy = fast_policy(true);
+        let client = reqwest::Client::new();
+        let url = format!("{}/broken", server.url());
+
+        let response = policy
+            .run(
+                "GET /broken",
+                Idempotency::Idempotent,
+                |r| policy.should_retry_http(r),
+                || execute(client.get(&url)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 500);
+        mock.assert_async().await;
+    }
+}
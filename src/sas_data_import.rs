@@ -1,149 +1,251 @@
 use crate::config::TestConfig;
+use crate::error::TigerClawError;
 use crate::merchant::Merchant;
-use log::{error, warn};
-use reqwest::header::AUTHORIZATION;
+use crate::orchestration_status::StepDescriptor;
+use crate::sas_data_import_client::SasDataImportClient;
+use crate::sas_resources::SasResource;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::env;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SasDataImport {
     pub status: String,
 }
 
-pub async fn run(test_config: &TestConfig) {
-    let client = reqwest::Client::new();
-    health_check(client.clone(), test_config).await;
-    let api_key = get_api_key().expect("Error: sas_data_import API key not found");
-
-    if test_config.globals.external_id.is_none() {
-        error!("No external_id provided",);
-        return;
-    } else if test_config.globals.external_id.unwrap() <= 0 {
-        error!(
-            "Invalid external_id: {}. Must be greater than 0",
-            test_config.globals.external_id.unwrap()
-        );
-        return;
-    }
+/// Runs every extraction, returning the first failure. The service's health
+/// is checked before any suite runs, see `ServiceHealth`
+pub async fn run(
+    client: &SasDataImportClient,
+    test_config: &TestConfig,
+) -> Result<(), TigerClawError> {
+    let external_id = match test_config.globals.external_id {
+        None => {
+            return Err(TigerClawError::Config(
+                "No external_id provided".to_string(),
+            ));
+        }
+        Some(external_id) if external_id <= 0 => {
+            return Err(TigerClawError::Config(format!(
+                "Invalid external_id: {}. Must be greater than 0",
+                external_id
+            )));
+        }
+        Some(external_id) => external_id,
+    };
 
-    let merchant = merchant_extraction(client.clone(), test_config, &api_key).await;
-    println!("Merchant extraction completed: {:?}", merchant);
+    let mut outcome = Ok(());
+    for resource in resources_to_extract(test_config) {
+        let extraction = match resource {
+            SasResource::Merchant => {
+                let merchant = merchant_extraction(client, external_id).await;
+                info!("Merchant extraction completed: {:?}", merchant);
+                merchant.map(|_| ())
+            }
+            resource => extract_resource(client, external_id, resource).await,
+        };
+        outcome = outcome.and(extraction);
+    }
+    outcome
 }
 
-fn get_api_key() -> Option<String> {
-    match env::var("AWIN_SAS_DATA_IMPORT_API_SECRET") {
-        Ok(secret) => {
-            Some(secret)
-        }
-        Err(_) => {
-            panic!("SAS Data Import API secret not found in environment variables");
+/// Resources listed in `[sas_data_import] resources`, or when none are listed the
+/// merchant plus the source data of the configured orchestration steps
+fn resources_to_extract(test_config: &TestConfig) -> Vec<SasResource> {
+    let configured = &test_config.sas_data_import.resources;
+    if !configured.is_empty() {
+        return configured
+            .iter()
+            .filter_map(|name| {
+                let resource = SasResource::from_string(name);
+                if resource.is_none() {
+                    warn!("Unknown SAS data import resource {}, skipping", name);
+                }
+                resource
+            })
+            .collect();
+    }
+
+    let mut resources = vec![SasResource::Merchant];
+    let steps =
+        StepDescriptor::parse_list(&test_config.orchestration.step_to_run).unwrap_or_default();
+    for step in steps {
+        for resource in SasResource::for_step(&step) {
+            if !resources.contains(&resource) {
+                resources.push(resource);
+            }
         }
     }
+    resources
 }
 
-
 /// Runs a ping to the SAS Data Import service to check if it is running on the given URL set above
-async fn health_check(client: reqwest::Client, test_config: &TestConfig) {
-    let health_check_url = format!(
-        "{}/actuator/health",
-        test_config.globals.base_sas_data_import_url
-    );
-    println!("running health check on: {}", health_check_url);
-    match client.get(health_check_url).send().await {
-        Ok(response) => {
-            println!(
-                "Received response from SAS Data Import service: {:?}",
-                response
-            );
-            match response.json::<SasDataImport>().await {
-                Ok(status) => {
-                    if status.status != "UP" {
-                        eprintln!(
-                            "ERROR:SAS Data Import service is not returning UP status: {:?}",
-                            status
-                        );
-                    }
-                    else {
-                        println!(
-                            "SAS Data Import service is running with status: {:?}",
-                            status.status
-                        );
-                    }
-                }
-                Err(error) => {
-                    eprintln!(
-                        "ERROR:Failed to parse response from SAS data import: {:?}",
-                        error
-                    );
-                }
+pub async fn health_check(client: &SasDataImportClient) -> Result<(), TigerClawError> {
+    info!("running SAS Data Import health check");
+    match client.health().await {
+        Ok(status) => {
+            if status.status != "UP" {
+                error!(
+                    "SAS Data Import service is not returning UP status: {:?}",
+                    status
+                );
+                return Err(TigerClawError::Assertion(format!(
+                    "SAS Data Import service status is {}",
+                    status.status
+                )));
             }
+            info!(
+                "SAS Data Import service is running with status: {:?}",
+                status.status
+            );
+            Ok(())
+        }
+        Err(error) if error.is_timeout() => {
+            error!("TIMED OUT:sas_data_import health check: {}", error);
+            Err(error)
         }
         Err(error) => {
-            eprintln!("ERROR:sas_data_import ping request error: {:?}", error);
+            error!("sas_data_import health check failed: {}", error);
+            Err(error)
         }
     }
 }
 
-async fn merchant_extraction(
-    client: reqwest::Client,
-    test_config: &TestConfig,
-    api_key: &str,
-) -> Result<Merchant, Box<dyn std::error::Error>> {
-    println!("Running merchant extraction...");
-    let url = format!(
-        "{}:{}/merchant/{}",
-        test_config.globals.base_sas_data_import_url,
-        test_config.globals.base_sas_data_import_port,
-        test_config.globals.external_id.unwrap()
+/// Fetches the merchant and checks the service returned the one asked for
+pub async fn merchant_extraction(
+    client: &SasDataImportClient,
+    external_id: i32,
+) -> Result<Merchant, TigerClawError> {
+    info!(
+        "Running merchant extraction for merchant {}...",
+        external_id
     );
-
-    println!("Extracting merchant data using URL: {}", url);
-    let response = client
-        .get(url)
-        .header(AUTHORIZATION, api_key)
-        .send()
-        .await?;
-
-    if response.status() != 200 {
-        warn!("Failed to extract merchant data: {:?}", response);
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to extract merchant data: {:?}", response.status()),
-        )));
-    }
-
-    match response.json::<Merchant>().await {
+    match client.merchant(external_id).await {
         Ok(merchant) => {
-            println!("Merchant data extracted successfully: {:?}", merchant);
+            if merchant.merchant_id != external_id as i64 {
+                let message = format!(
+                    "Merchant ID mismatch: requested {} but received {}",
+                    external_id, merchant.merchant_id
+                );
+                error!("{}", message);
+                return Err(TigerClawError::Assertion(message));
+            }
+            info!("Merchant data extracted successfully: {:?}", merchant);
             Ok(merchant)
         }
+        Err(e) if e.is_timeout() => {
+            error!("TIMED OUT: extracting merchant data: {}", e);
+            Err(e)
+        }
         Err(e) => {
-            eprintln!("Failed to parse merchant data: {:?}", e);
-            return Err(Box::new(e));
+            error!("Failed to extract merchant data: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Pulls one of the merchant's list resources and checks every record belongs
+/// to the merchant we asked for
+async fn extract_resource(
+    client: &SasDataImportClient,
+    external_id: i32,
+    resource: SasResource,
+) -> Result<(), TigerClawError> {
+    info!(
+        "Extracting {} for merchant {}...",
+        resource.name(),
+        external_id
+    );
+    let merchant_ids: Result<Vec<Option<i64>>, TigerClawError> = match resource {
+        SasResource::Merchant => return Ok(()),
+        SasResource::Program => client
+            .program(external_id)
+            .await
+            .map(|program| vec![Some(program.merchant_id)]),
+        SasResource::Affiliates => client
+            .affiliates(external_id)
+            .await
+            .map(|items| items.iter().map(|item| item.merchant_id).collect()),
+        SasResource::Commissions => client
+            .commissions(external_id)
+            .await
+            .map(|items| items.iter().map(|item| item.merchant_id).collect()),
+        SasResource::Vouchers => client
+            .vouchers(external_id)
+            .await
+            .map(|items| items.iter().map(|item| item.merchant_id).collect()),
+        SasResource::Feeds => client
+            .feeds(external_id)
+            .await
+            .map(|items| items.iter().map(|item| item.merchant_id).collect()),
+        SasResource::Creatives => client
+            .creatives(external_id)
+            .await
+            .map(|items| items.iter().map(|item| item.merchant_id).collect()),
+    };
+
+    match merchant_ids {
+        Ok(merchant_ids) => {
+            let mismatched = merchant_ids
+                .iter()
+                .flatten()
+                .filter(|id| **id != external_id as i64)
+                .count();
+            if mismatched > 0 {
+                let message = format!(
+                    "{} of {} {} records belong to a different merchant",
+                    mismatched,
+                    merchant_ids.len(),
+                    resource.name()
+                );
+                error!("{}", message);
+                return Err(TigerClawError::Assertion(message));
+            }
+            info!(
+                "Extracted {} {} records for merchant {}",
+                merchant_ids.len(),
+                resource.name(),
+                external_id
+            );
+            Ok(())
+        }
+        Err(error) if error.is_timeout() => {
+            error!("TIMED OUT: extracting {}: {}", resource.name(), error);
+            Err(error)
+        }
+        Err(error) => {
+            error!("Failed to extract {}: {}", resource.name(), error);
+            Err(error)
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::{self, load_test_config};
+    use crate::auth::StaticApiKey;
+    use crate::config;
+    use std::sync::Arc;
 
     use super::*;
 
     #[tokio::test]
     async fn test_health_check() {
-        let client = reqwest::Client::new();
         let test_config = config::load_test_config("tests.local.toml").unwrap();
-        health_check(client, &test_config).await;
+        let client = SasDataImportClient::new(
+            &test_config.globals,
+            Arc::new(StaticApiKey::new("Authorization", "unused")),
+        )
+        .unwrap();
+        // Only checks the health check doesn't panic, the service may not be running
+        let _ = health_check(&client).await;
     }
 
     #[tokio::test]
     async fn test_merchant_extraction() {
-        let client = reqwest::Client::new();
-        let mut test_config = config::load_test_config("tests.local.toml").unwrap();
-        let api_key = get_api_key().expect("API key not found");
-        test_config.globals.external_id = Some(44911);
-        match merchant_extraction(client, &test_config, &api_key).await {
+        let test_config = config::load_test_config("tests.local.toml").unwrap();
+        let auth = StaticApiKey::from_env("Authorization", "AWIN_SAS_DATA_IMPORT_API_SECRET")
+            .expect("API key not found");
+        let client = SasDataImportClient::new(&test_config.globals, Arc::new(auth)).unwrap();
+        match merchant_extraction(&client, 44911).await {
             Ok(merchant) => {
                 assert!(
                     merchant.merchant_id == 44911,
@@ -155,4 +257,29 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn resources_default_to_the_orchestration_step() {
+        let mut test_config = config::load_test_config("tests.local.toml").unwrap();
+        test_config.orchestration.step_to_run = "COM".to_string();
+        test_config.sas_data_import.resources = Vec::new();
+        assert_eq!(
+            resources_to_extract(&test_config),
+            vec![SasResource::Merchant, SasResource::Commissions]
+        );
+
+        test_config.orchestration.step_to_run = "VOUCH..COM".to_string();
+        assert_eq!(
+            resources_to_extract(&test_config),
+            vec![
+                SasResource::Merchant,
+                SasResource::Vouchers,
+                SasResource::Affiliates,
+                SasResource::Commissions,
+            ]
+        );
+
+        test_config.sas_data_import.resources = vec!["feeds".to_string(), "nope".to_string()];
+        assert_eq!(resources_to_extract(&test_config), vec![SasResource::Feeds]);
+    }
 }
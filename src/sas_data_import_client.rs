@@ -0,0 +1,278 @@
+use crate::auth::AuthProvider;
+use crate::base_url;
+use crate::config::GlobalConfig;
+use crate::error::TigerClawError;
+use crate::merchant::Merchant;
+use crate::request::{self, BufferedResponse, send_with_retry};
+use crate::retry::{Idempotency, RetryPolicy};
+use crate::sas_data_import::SasDataImport;
+use crate::sas_resources::{Affiliate, Commission, Creative, Feed, Program, SasResource, Voucher};
+use crate::timeouts::{self, ServiceTimeouts};
+use log::info;
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Client for the SAS data import service, which exposes the SAS source data
+/// each orchestration step migrates. Read only, built once per run
+pub struct SasDataImportClient {
+    http: reqwest::Client,
+    base_url: Url,
+    auth: Arc<dyn AuthProvider>,
+    retry: RetryPolicy,
+    dry_run: bool,
+    endpoints: HashMap<String, String>,
+}
+
+impl SasDataImportClient {
+    pub fn new(
+        globals: &GlobalConfig,
+        auth: Arc<dyn AuthProvider>,
+    ) -> Result<Self, TigerClawError> {
+        Ok(SasDataImportClient {
+            http: timeouts::http_client(ServiceTimeouts::default())
+                .map_err(TigerClawError::Config)?,
+            base_url: globals
+                .sas_data_import_base_url()
+                .map_err(TigerClawError::Config)?,
+            auth,
+            retry: RetryPolicy::default(),
+            dry_run: false,
+            endpoints: HashMap::new(),
+        })
+    }
+
+    /// Retry transient failures with `retry` instead of the default policy
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Print every request as it is sent. Nothing here mutates, so a dry run
+    /// still sends them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Read resources from the paths in `endpoints`, keyed by resource name,
+    /// instead of `SasResource::default_path`. Unknown names are ignored, the
+    /// config check reports them
+    pub fn with_endpoints(mut self, endpoints: &HashMap<String, String>) -> Self {
+        self.endpoints = endpoints
+            .iter()
+            .filter_map(|(name, path)| {
+                SasResource::from_string(name)
+                    .map(|resource| (resource.name().to_string(), path.clone()))
+            })
+            .collect();
+        self
+    }
+
+    /// Use the service's configured timeouts instead of the defaults
+    pub fn with_timeouts(mut self, timeouts: ServiceTimeouts) -> Result<Self, TigerClawError> {
+        self.http = timeouts::http_client(timeouts).map_err(TigerClawError::Config)?;
+        Ok(self)
+    }
+
+    /// Spring actuator health, doesn't need credentials
+    pub async fn health(&self) -> Result<SasDataImport, TigerClawError> {
+        let url = self.url("actuator/health");
+        self.print_dry_run(&url);
+        let response = request::execute(self.http.get(url))
+            .await
+            .map_err(TigerClawError::from)?;
+        check_status(response)?.json()
+    }
+
+    pub async fn merchant(&self, merchant_id: i32) -> Result<Merchant, TigerClawError> {
+        self.get(&self.path(SasResource::Merchant, merchant_id))
+            .await
+    }
+
+    /// Affiliates (publishers) joined to the merchant's program
+    pub async fn affiliates(&self, merchant_id: i32) -> Result<Vec<Affiliate>, TigerClawError> {
+        self.get(&self.path(SasResource::Affiliates, merchant_id))
+            .await
+    }
+
+    pub async fn program(&self, merchant_id: i32) -> Result<Program, TigerClawError> {
+        self.get(&self.path(SasResource::Program, merchant_id))
+            .await
+    }
+
+    pub async fn commissions(&self, merchant_id: i32) -> Result<Vec<Commission>, TigerClawError> {
+        self.get(&self.path(SasResource::Commissions, merchant_id))
+            .await
+    }
+
+    pub async fn vouchers(&self, merchant_id: i32) -> Result<Vec<Voucher>, TigerClawError> {
+        self.get(&self.path(SasResource::Vouchers, merchant_id))
+            .await
+    }
+
+    pub async fn feeds(&self, merchant_id: i32) -> Result<Vec<Feed>, TigerClawError> {
+        self.get(&self.path(SasResource::Feeds, merchant_id)).await
+    }
+
+    pub async fn creatives(&self, merchant_id: i32) -> Result<Vec<Creative>, TigerClawError> {
+        self.get(&self.path(SasResource::Creatives, merchant_id))
+            .await
+    }
+
+    fn path(&self, resource: SasResource, merchant_id: i32) -> String {
+        self.endpoints
+            .get(resource.name())
+            .map_or(resource.default_path(), String::as_str)
+            .replace("{merchant_id}", &merchant_id.to_string())
+    }
+
+    fn url(&self, endpoint_path: &str) -> Url {
+        base_url::join(&self.base_url, endpoint_path)
+    }
+
+    fn print_dry_run(&self, url: &Url) {
+        if self.dry_run {
+            info!("Dry run, sending GET {}", url);
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, endpoint_path: &str) -> Result<T, TigerClawError> {
+        let url = self.url(endpoint_path);
+        self.print_dry_run(&url);
+        let response = send_with_retry(
+            self.auth.as_ref(),
+            &self.retry,
+            Idempotency::Idempotent,
+            || self.http.get(url.clone()),
+        )
+        .await
+        .map_err(TigerClawError::from)?;
+        check_status(response)?.json()
+    }
+}
+
+fn check_status(response: BufferedResponse) -> Result<BufferedResponse, TigerClawError> {
+    if response.status() == StatusCode::OK {
+        return Ok(response);
+    }
+    Err(TigerClawError::from_response(&response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::StaticApiKey;
+
+    fn client(server: &mockito::Server) -> SasDataImportClient {
+        let globals = GlobalConfig {
+            environment: "local".to_string(),
+            sas_data_import_url: Some(server.url()),
+            ..GlobalConfig::default()
+        };
+        SasDataImportClient::new(
+            &globals,
+            Arc::new(StaticApiKey::new("Authorization", "sas-client-test-key")),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn list_resources_are_decoded() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/merchant/44911/vouchers")
+            .match_header("authorization", "sas-client-test-key")
+            .with_status(200)
+            .with_body(r#"[{"voucherId":1,"merchantId":44911,"code":"SAVE10"},{"voucherId":2}]"#)
+            .create_async()
+            .await;
+
+        let vouchers = client(&server).vouchers(44911).await.unwrap();
+        assert_eq!(vouchers.len(), 2);
+        assert_eq!(vouchers[0].code.as_deref(), Some("SAVE10"));
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn configured_endpoints_replace_the_default_paths() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/sas/codes/44911")
+            .with_status(200)
+            .with_body("[]")
+            .create_async()
+            .await;
+        let endpoints = HashMap::from([(
+            "Vouchers".to_string(),
+            "sas/codes/{merchant_id}".to_string(),
+        )]);
+
+        let vouchers = client(&server)
+            .with_endpoints(&endpoints)
+            .vouchers(44911)
+            .await
+            .unwrap();
+        assert!(vouchers.is_empty());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn missing_resource_is_not_found() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/merchant/1/program")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let error = client(&server).program(1).await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn health_reports_status() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/actuator/health")
+            .with_status(200)
+            .with_body(r#"{"status":"UP"}"#)
+            .create_async()
+            .await;
+
+        assert_eq!(client(&server).health().await.unwrap().status, "UP");
+    }
+
+    #[tokio::test]
+    async fn hung_service_is_reported_as_timed_out() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let _server = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+        });
+        let globals = GlobalConfig {
+            environment: "local".to_string(),
+            sas_data_import_url: Some(format!("http://{}", address)),
+            ..GlobalConfig::default()
+        };
+        let client = SasDataImportClient::new(
+            &globals,
+            Arc::new(StaticApiKey::new("Authorization", "sas-client-test-key")),
+        )
+        .unwrap()
+        .with_timeouts(ServiceTimeouts {
+            connect: std::time::Duration::from_secs(1),
+            read: std::time::Duration::from_millis(200),
+        })
+        .unwrap();
+
+        let result = client.merchant(1).await;
+        assert!(
+            result.as_ref().is_err_and(TigerClawError::is_timeout),
+            "{:?}",
+            result
+        );
+    }
+}
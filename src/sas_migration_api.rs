@@ -1,76 +1,325 @@
-use crate::config::TestConfig;
-use crate::request::growth_migration_post;
+use crate::error::TigerClawError;
+use crate::growth_migration::GrowthMigrationClient;
+use crate::request::BufferedResponse;
+use crate::sas_data_import_client::SasDataImportClient;
+use log::{error, info};
 
-pub async fn run(merchant_id: &i32, token: &str, test_config: &TestConfig) {
+/// Lock state of a SAS merchant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Locks {
+    pub lockdown: bool,
+    pub fee_lock: bool,
+}
+
+impl Locks {
+    pub const LOCKED: Locks = Locks {
+        lockdown: true,
+        fee_lock: true,
+    };
+    pub const UNLOCKED: Locks = Locks {
+        lockdown: false,
+        fee_lock: false,
+    };
+}
+
+/// One of the `sasMigrationApi` lock requests, with the words used to log it
+#[derive(Debug, Clone, Copy)]
+enum LockRequest {
+    Lockdown,
+    FeeLock,
+    Unlock,
+    FeeUnlock,
+}
+
+impl LockRequest {
+    fn verb(self) -> &'static str {
+        match self {
+            LockRequest::Lockdown => "lock",
+            LockRequest::FeeLock => "fee lock",
+            LockRequest::Unlock => "unlock",
+            LockRequest::FeeUnlock => "fee unlock",
+        }
+    }
+
+    fn done(self) -> &'static str {
+        match self {
+            LockRequest::Lockdown => "Lockdown enabled",
+            LockRequest::FeeLock => "Fee lock enabled",
+            LockRequest::Unlock => "Lockdown lifted",
+            LockRequest::FeeUnlock => "Fee lock lifted",
+        }
+    }
+
+    async fn send(
+        self,
+        merchant_id: i32,
+        client: &GrowthMigrationClient,
+    ) -> Result<BufferedResponse, TigerClawError> {
+        match self {
+            LockRequest::Lockdown => client.lockdown(merchant_id).await,
+            LockRequest::FeeLock => client.fee_lock(merchant_id).await,
+            LockRequest::Unlock => client.unlock(merchant_id).await,
+            LockRequest::FeeUnlock => client.fee_unlock(merchant_id).await,
+        }
+    }
+}
+
+/// Locks the merchant down and locks its fees. Both are attempted even if the
+/// first fails, the first failure is returned. The merchant record is read
+/// back from `merchants` afterwards to check both locks took
+pub async fn run(
+    merchant_id: &i32,
+    client: &GrowthMigrationClient,
+    merchants: &SasDataImportClient,
+) -> Result<(), TigerClawError> {
+    set_locks(
+        merchant_id,
+        client,
+        merchants,
+        [LockRequest::Lockdown, LockRequest::FeeLock],
+        Locks::LOCKED,
+    )
+    .await
+}
+
+/// Lifts the lockdown and the fee lock so a test merchant is back to normal,
+/// the inverse of `run`
+pub async fn unlock(
+    merchant_id: &i32,
+    client: &GrowthMigrationClient,
+    merchants: &SasDataImportClient,
+) -> Result<(), TigerClawError> {
+    set_locks(
+        merchant_id,
+        client,
+        merchants,
+        [LockRequest::Unlock, LockRequest::FeeUnlock],
+        Locks::UNLOCKED,
+    )
+    .await
+}
+
+async fn set_locks(
+    merchant_id: &i32,
+    client: &GrowthMigrationClient,
+    merchants: &SasDataImportClient,
+    requests: [LockRequest; 2],
+    expected: Locks,
+) -> Result<(), TigerClawError> {
     // Validate that the merchant_id is greater than 0
     if *merchant_id <= 0 {
-        let error_message = format!(
+        return Err(TigerClawError::Config(format!(
             "Invalid merchant_id: {}. Must be greater than 0",
             merchant_id
-        );
-        panic!("{}", error_message);
+        )));
+    }
+
+    let mut outcome = Ok(());
+    for request in requests {
+        outcome = outcome.and(send(*merchant_id, request, client).await);
     }
+    outcome?;
 
-    enable_lockdown(merchant_id, token, test_config).await;
-    enable_fee_lock(merchant_id, token, test_config).await;
+    if client.dry_run() {
+        info!(
+            "Dry run, not verifying the locks of merchant_id {}, nothing was sent",
+            merchant_id
+        );
+        return Ok(());
+    }
+    verify(*merchant_id, merchants, expected).await
 }
 
-async fn enable_lockdown(merchant_id: &i32, token: &str, test_config: &TestConfig) {
-    println!("sending lockdown request for mid:{}", merchant_id);
-    let endpoint_path = format!("sasMigrationApi/lockdown/{}", merchant_id);
-    match growth_migration_post(&endpoint_path, token.to_string(), test_config).await {
+async fn send(
+    merchant_id: i32,
+    request: LockRequest,
+    client: &GrowthMigrationClient,
+) -> Result<(), TigerClawError> {
+    info!("sending {} request for mid:{}", request.verb(), merchant_id);
+    match request.send(merchant_id, client).await {
         Ok(response) => {
-            println!("Response: {:?}", response);
-            if response.status().is_success() {
-                println!(
-                    "SAS migration started successfully for merchant_id: {}",
-                    merchant_id
-                );
-            } else {
-                let error_message = format!(
-                    "Failed to lock merchant_id: {}. Status: {}",
-                    merchant_id,
-                    response.status()
-                );
-                eprintln!("{}", error_message);
-            }
+            info!(
+                "{} for merchant_id: {}. Status: {}",
+                request.done(),
+                merchant_id,
+                response.status()
+            );
+            Ok(())
+        }
+        Err(e) if e.is_timeout() => {
+            error!(
+                "TIMED OUT: {} request for merchant_id {}: {}",
+                request.verb(),
+                merchant_id,
+                e
+            );
+            Err(e)
         }
         Err(e) => {
-            eprintln!(
-                "Failed to lock merchant_id: {}. Error Message: {}",
-                merchant_id, e
+            error!(
+                "Failed to {} merchant_id: {}. Error Message: {}",
+                request.verb(),
+                merchant_id,
+                e
             );
+            Err(e)
         }
-    };
+    }
 }
 
-async fn enable_fee_lock(merchant_id: &i32, token: &str, test_config: &TestConfig) {
-    println!("Enabling fee lock...");
-    let endpoint_path = format!("sasMigrationApi/feelock/{}", merchant_id);
-    match growth_migration_post(&endpoint_path, token.to_string(), test_config).await {
-        Ok(response) => {
-            println!("Response: {:?}", response);
-            if response.status().is_success() {
-                println!(
-                    "SAS migration started successfully for merchant_id: {}",
-                    merchant_id
-                );
-            } else {
-                let error_message = format!(
-                    "response Failed to fee lock merchant_id: {}. Status: {}",
-                    merchant_id,
-                    response.status()
-                );
-                eprintln!("{}", error_message);
-            }
+/// Reads the merchant back from SAS data import and checks its locks are
+/// `expected`. A record that doesn't carry a lock's state fails too, the
+/// locks can't be verified from it
+pub async fn verify(
+    merchant_id: i32,
+    merchants: &SasDataImportClient,
+    expected: Locks,
+) -> Result<(), TigerClawError> {
+    let merchant = merchants.merchant(merchant_id).await?;
+    let mut wrong = Vec::new();
+    for (name, actual, expected) in [
+        ("locked down", merchant.locked_down, expected.lockdown),
+        ("fee locked", merchant.fee_locked, expected.fee_lock),
+    ] {
+        match actual {
+            Some(actual) if actual != expected => wrong.push(format!(
+                "{}{}",
+                if expected { "not " } else { "still " },
+                name
+            )),
+            Some(_) => {}
+            None => wrong.push(format!("not known to be {}", name)),
         }
-        Err(e) => {
-            println!("Error: {:?}", e);
-            let error_message = format!(
-                "Failed to fee lock merchant_id: {}. Error Message: {}",
-                merchant_id, e
-            );
-            eprintln!("{}", error_message);
+    }
+    if !wrong.is_empty() {
+        let message = format!("Merchant {} is {}", merchant_id, wrong.join(" and "));
+        error!("{}", message);
+        return Err(TigerClawError::Assertion(message));
+    }
+    info!("Verified the locks of merchant_id {}", merchant_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::StaticApiKey;
+    use crate::config::GlobalConfig;
+    use std::sync::Arc;
+
+    fn merchant_body(locked_down: &str, fee_locked: &str) -> String {
+        let mut merchant =
+            serde_json::to_value(crate::merchant::Merchant::test_merchant()).unwrap();
+        merchant["lockedDown"] = serde_json::from_str(locked_down).unwrap();
+        merchant["feeLocked"] = serde_json::from_str(fee_locked).unwrap();
+        merchant.to_string()
+    }
+
+    fn clients(server: &mockito::Server) -> (GrowthMigrationClient, SasDataImportClient) {
+        let globals = GlobalConfig {
+            environment: "local".to_string(),
+            growth_migration_url: Some(server.url()),
+            sas_data_import_url: Some(server.url()),
+            ..GlobalConfig::default()
+        };
+        let auth = Arc::new(StaticApiKey::new("Authorization", "lock-test-key"));
+        (
+            GrowthMigrationClient::new(&globals, auth.clone()).unwrap(),
+            SasDataImportClient::new(&globals, auth).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn locks_are_verified_against_the_merchant_record() {
+        let mut server = mockito::Server::new_async().await;
+        for endpoint in ["lockdown", "feelock"] {
+            server
+                .mock(
+                    "POST",
+                    format!("/sasMigrationApi/{}/44911", endpoint).as_str(),
+                )
+                .with_status(200)
+                .create_async()
+                .await;
         }
-    };
+        let half_locked = server
+            .mock("GET", "/merchant/44911")
+            .with_status(200)
+            .with_body(merchant_body("true", "false"))
+            .create_async()
+            .await;
+        let (client, merchants) = clients(&server);
+
+        let error = run(&44911, &client, &merchants).await.unwrap_err();
+        assert!(matches!(error, TigerClawError::Assertion(_)));
+        assert!(error.to_string().contains("not fee locked"), "{}", error);
+        half_locked.remove_async().await;
+
+        server
+            .mock("GET", "/merchant/44911")
+            .with_status(200)
+            .with_body(merchant_body("true", "true"))
+            .create_async()
+            .await;
+        run(&44911, &client, &merchants).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn records_without_the_lock_state_fail_verification() {
+        let mut server = mockito::Server::new_async().await;
+        for endpoint in ["lockdown", "feelock"] {
+            server
+                .mock(
+                    "POST",
+                    format!("/sasMigrationApi/{}/44911", endpoint).as_str(),
+                )
+                .with_status(200)
+                .create_async()
+                .await;
+        }
+        let mut merchant =
+            serde_json::to_value(crate::merchant::Merchant::test_merchant()).unwrap();
+        merchant.as_object_mut().unwrap().remove("lockedDown");
+        merchant.as_object_mut().unwrap().remove("feeLocked");
+        server
+            .mock("GET", "/merchant/44911")
+            .with_status(200)
+            .with_body(merchant.to_string())
+            .create_async()
+            .await;
+        let (client, merchants) = clients(&server);
+
+        let error = run(&44911, &client, &merchants).await.unwrap_err();
+        assert!(matches!(error, TigerClawError::Assertion(_)));
+        assert_eq!(
+            error.to_string(),
+            "Check failed: Merchant 44911 is not known to be locked down and not known to be fee locked"
+        );
+    }
+
+    #[tokio::test]
+    async fn unlocking_checks_both_locks_are_lifted() {
+        let mut server = mockito::Server::new_async().await;
+        for endpoint in ["unlock", "feeunlock"] {
+            server
+                .mock(
+                    "POST",
+                    format!("/sasMigrationApi/{}/44911", endpoint).as_str(),
+                )
+                .with_status(204)
+                .expect(1)
+                .create_async()
+                .await;
+        }
+        server
+            .mock("GET", "/merchant/44911")
+            .with_status(200)
+            .with_body(merchant_body("true", "false"))
+            .create_async()
+            .await;
+        let (client, merchants) = clients(&server);
+
+        let error = unlock(&44911, &client, &merchants).await.unwrap_err();
+        assert!(error.to_string().contains("still locked down"), "{}", error);
+    }
 }
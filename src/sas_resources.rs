@@ -0,0 +1,173 @@
+use crate::orchestration_status::StepDescriptor;
+use serde::{Deserialize, Serialize};
+
+/// Models for the SAS data import resources a migration reads from. Only the
+/// identifiers are required, everything else is optional so a field missing on
+/// one merchant doesn't fail the whole extraction
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Affiliate {
+    pub affiliate_id: i64,
+    pub merchant_id: Option<i64>,
+    pub organization: Option<String>,
+    pub website: Option<String>,
+    pub status: Option<String>,
+    pub awin_publisher_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Program {
+    pub merchant_id: i64,
+    pub program_name: Option<String>,
+    pub status: Option<String>,
+    pub commission_rate: Option<f64>,
+    pub cookie_length: Option<i32>,
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Commission {
+    pub commission_id: i64,
+    pub merchant_id: Option<i64>,
+    pub affiliate_id: Option<i64>,
+    pub commission_type: Option<String>,
+    pub rate: Option<f64>,
+    pub amount: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Voucher {
+    pub voucher_id: i64,
+    pub merchant_id: Option<i64>,
+    pub code: Option<String>,
+    pub description: Option<String>,
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Feed {
+    pub feed_id: i64,
+    pub merchant_id: Option<i64>,
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub format: Option<String>,
+    pub last_updated: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Creative {
+    pub creative_id: i64,
+    pub merchant_id: Option<i64>,
+    pub name: Option<String>,
+    pub creative_type: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub url: Option<String>,
+}
+
+/// The resources that can be pulled from SAS data import for a merchant
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SasResource {
+    Merchant,
+    Affiliates,
+    Program,
+    Commissions,
+    Vouchers,
+    Feeds,
+    Creatives,
+}
+
+impl SasResource {
+    pub fn from_string(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "merchant" => Some(SasResource::Merchant),
+            "affiliates" | "publishers" => Some(SasResource::Affiliates),
+            "program" | "programs" => Some(SasResource::Program),
+            "commissions" => Some(SasResource::Commissions),
+            "vouchers" => Some(SasResource::Vouchers),
+            "feeds" => Some(SasResource::Feeds),
+            "creatives" => Some(SasResource::Creatives),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SasResource::Merchant => "merchant",
+            SasResource::Affiliates => "affiliates",
+            SasResource::Program => "program",
+            SasResource::Commissions => "commissions",
+            SasResource::Vouchers => "vouchers",
+            SasResource::Feeds => "feeds",
+            SasResource::Creatives => "creatives",
+        }
+    }
+
+    /// Where the resource is read from, relative to the service's base URL,
+    /// with `{merchant_id}` standing in for the merchant. Only the merchant
+    /// route is one the service is known to serve, the others are assumed
+    /// until its API documents them and can be replaced in
+    /// `[sas_data_import.endpoints]`
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            SasResource::Merchant => "merchant/{merchant_id}",
+            SasResource::Affiliates => "merchant/{merchant_id}/affiliates",
+            SasResource::Program => "merchant/{merchant_id}/program",
+            SasResource::Commissions => "merchant/{merchant_id}/commissions",
+            SasResource::Vouchers => "merchant/{merchant_id}/vouchers",
+            SasResource::Feeds => "merchant/{merchant_id}/feeds",
+            SasResource::Creatives => "merchant/{merchant_id}/creatives",
+        }
+    }
+
+    /// The source data an orchestration step migrates, so the step's input can
+    /// be pulled and checked before or after it runs
+    pub fn for_step(step: &StepDescriptor) -> Vec<SasResource> {
+        match step {
+            StepDescriptor::Init | StepDescriptor::Valid | StepDescriptor::Sf => {
+                vec![SasResource::Merchant]
+            }
+            StepDescriptor::Adv => vec![SasResource::Merchant, SasResource::Program],
+            StepDescriptor::Pub | StepDescriptor::MemTag => vec![SasResource::Affiliates],
+            StepDescriptor::Track | StepDescriptor::Fee => vec![SasResource::Program],
+            StepDescriptor::Vouch => vec![SasResource::Vouchers],
+            StepDescriptor::Com => vec![SasResource::Commissions],
+            StepDescriptor::Feed => vec![SasResource::Feeds],
+            StepDescriptor::Creative => vec![SasResource::Creatives],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resources_parse_case_insensitively() {
+        assert_eq!(
+            SasResource::from_string("Publishers"),
+            Some(SasResource::Affiliates)
+        );
+        assert_eq!(SasResource::from_string("FEEDS"), Some(SasResource::Feeds));
+        assert_eq!(SasResource::from_string("widgets"), None);
+    }
+
+    #[test]
+    fn step_maps_to_its_source_data() {
+        assert_eq!(
+            SasResource::for_step(&StepDescriptor::Com),
+            vec![SasResource::Commissions]
+        );
+        assert_eq!(
+            SasResource::for_step(&StepDescriptor::Adv),
+            vec![SasResource::Merchant, SasResource::Program]
+        );
+    }
+}
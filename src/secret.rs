@@ -0,0 +1,289 @@
+use crate::auth::BoxFuture;
+use crate::redact;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Where a credential is read from, written in config as `env:NAME`,
+/// `file:/path/to/secret` or `aws-ssm:/parameter/name`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum SecretRef {
+    Env(String),
+    File(PathBuf),
+    AwsSsm(String),
+}
+
+impl SecretRef {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (scheme, name) = value.split_once(':').ok_or_else(|| {
+            format!(
+                "Invalid secret reference '{}', expected env:NAME, file:/path or aws-ssm:/name",
+                value
+            )
+        })?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("Secret reference '{}' has no name", value));
+        }
+        match scheme.trim() {
+            "env" => Ok(SecretRef::Env(name.to_string())),
+            "file" => Ok(SecretRef::File(PathBuf::from(name))),
+            "aws-ssm" => Ok(SecretRef::AwsSsm(name.to_string())),
+            scheme => Err(format!(
+                "Unknown secret source '{}' in '{}', expected env, file or aws-ssm",
+                scheme, value
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for SecretRef {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        SecretRef::parse(&value)
+    }
+}
+
+impl From<SecretRef> for String {
+    fn from(secret: SecretRef) -> Self {
+        secret.to_string()
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecretRef::Env(name) => write!(f, "env:{}", name),
+            SecretRef::File(path) => write!(f, "file:{}", path.display()),
+            SecretRef::AwsSsm(name) => write!(f, "aws-ssm:{}", name),
+        }
+    }
+}
+
+/// Looks up `aws-ssm:` parameters
+pub trait ParameterStore: Send + Sync {
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<String, String>>;
+}
+
+/// SSM Parameter Store, using the credentials and region of the default AWS
+/// chain, e.g. `AWS_PROFILE`. The client is only built once a parameter is
+/// needed, so runs without `aws-ssm:` references never touch AWS
+#[derive(Default)]
+pub struct AwsParameterStore {
+    client: OnceCell<aws_sdk_ssm::Client>,
+}
+
+impl ParameterStore for AwsParameterStore {
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<String, String>> {
+        Box::pin(async move {
+            let client = self
+                .client
+                .get_or_init(|| async {
+                    aws_sdk_ssm::Client::new(&aws_config::load_from_env().await)
+                })
+                .await;
+            let output = client
+                .get_parameter()
+                .name(name)
+                .with_decryption(true)
+                .send()
+                .await
+                .map_err(|e| format!("Failed to read SSM parameter {}: {}", name, e))?;
+            output
+                .parameter()
+                .and_then(|parameter| parameter.value())
+                .map(str::to_string)
+                .ok_or_else(|| format!("SSM parameter {} has no value", name))
+        })
+    }
+}
+
+/// Parameters held in memory, a stand-in for SSM in tests and local runs
+#[derive(Default)]
+pub struct LocalParameterStore {
+    parameters: HashMap<String, String>,
+}
+
+impl LocalParameterStore {
+    pub fn new<I, K, V>(parameters: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        LocalParameterStore {
+            parameters: parameters
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        }
+    }
+}
+
+impl ParameterStore for LocalParameterStore {
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<String, String>> {
+        let value = self
+            .parameters
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("SSM parameter {} not found", name));
+        Box::pin(async move { value })
+    }
+}
+
+/// Reads secrets from wherever their reference points. Every value read is
+/// registered for redaction. SSM parameters are fetched once per run, env
+/// vars and files are read every time so a rotated token file is picked up
+pub struct SecretResolver {
+    parameters: Arc<dyn ParameterStore>,
+    env: Option<HashMap<String, String>>,
+    fetched: Mutex<HashMap<String, String>>,
+}
+
+impl Default for SecretResolver {
+    fn default() -> Self {
+        SecretResolver::new(Arc::new(AwsParameterStore::default()))
+    }
+}
+
+impl SecretResolver {
+    pub fn new(parameters: Arc<dyn ParameterStore>) -> Self {
+        SecretResolver {
+            parameters,
+            env: None,
+            fetched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads `env:` references from `vars` instead of the process environment
+    pub fn with_env<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env = Some(
+            vars.into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        );
+        self
+    }
+
+    pub async fn resolve(&self, secret: &SecretRef) -> Result<String, String> {
+        let value = match secret {
+            SecretRef::Env(name) => match &self.env {
+                Some(vars) => vars.get(name).cloned(),
+                None => env::var(name).ok(),
+            }
+            .ok_or_else(|| format!("Environment variable {} is not set", name))?,
+            SecretRef::File(path) => fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+            SecretRef::AwsSsm(name) => {
+                let fetched = self.fetched(name);
+                match fetched {
+                    Some(value) => value,
+                    None => {
+                        let value = self.parameters.get(name).await?;
+                        self.lock().insert(name.clone(), value.clone());
+                        value
+                    }
+                }
+            }
+        };
+        let value = value.trim().to_string();
+        if value.is_empty() {
+            return Err(format!("Secret {} is empty", secret));
+        }
+        redact::register_secret(&value);
+        Ok(value)
+    }
+
+    /// Drops a fetched SSM parameter so the next `resolve` reads it again.
+    /// Returns false for env vars and files, which are never held on to
+    pub fn forget(&self, secret: &SecretRef) -> bool {
+        match secret {
+            SecretRef::AwsSsm(name) => {
+                self.lock().remove(name);
+                true
+            }
+            SecretRef::Env(_) | SecretRef::File(_) => false,
+        }
+    }
+
+    fn fetched(&self, name: &str) -> Option<String> {
+        self.lock().get(name).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        self.fetched.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn references_parse_and_print_back() {
+        for value in [
+            "env:AWIN_SECRET",
+            "file:/run/secrets/key",
+            "aws-ssm:/springfield/dev/secret",
+        ] {
+            assert_eq!(SecretRef::parse(value).unwrap().to_string(), value);
+        }
+        assert_eq!(
+            SecretRef::parse("aws-ssm:/a/b").unwrap(),
+            SecretRef::AwsSsm("/a/b".to_string())
+        );
+        assert!(SecretRef::parse("AWIN_SECRET").is_err());
+        assert!(SecretRef::parse("vault:secret/x").is_err());
+        assert!(SecretRef::parse("env:").is_err());
+    }
+
+    #[tokio::test]
+    async fn secrets_resolve_from_every_source() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "secret-test-file-value").unwrap();
+        let secrets = SecretResolver::new(Arc::new(LocalParameterStore::new([(
+            "/tiger-claw/test",
+            "secret-test-ssm-value",
+        )])))
+        .with_env([("TIGER_CLAW_SECRET_TEST_ENV", "secret-test-env-value")]);
+
+        let resolve = |value: &str| {
+            let secret = SecretRef::parse(value).unwrap();
+            let secrets = &secrets;
+            async move { secrets.resolve(&secret).await }
+        };
+        assert_eq!(
+            resolve("env:TIGER_CLAW_SECRET_TEST_ENV").await.unwrap(),
+            "secret-test-env-value"
+        );
+        assert_eq!(
+            resolve(&format!("file:{}", file.path().display()))
+                .await
+                .unwrap(),
+            "secret-test-file-value"
+        );
+        assert_eq!(
+            resolve("aws-ssm:/tiger-claw/test").await.unwrap(),
+            "secret-test-ssm-value"
+        );
+        assert!(redact::redact("secret-test-ssm-value").contains(redact::MASK));
+
+        let error = resolve("aws-ssm:/tiger-claw/missing").await.unwrap_err();
+        assert!(error.contains("/tiger-claw/missing"), "{}", error);
+        assert!(resolve("env:TIGER_CLAW_SECRET_TEST_UNSET").await.is_err());
+    }
+}
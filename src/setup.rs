@@ -1,8 +1,16 @@
+use crate::commands::Command;
+use crate::config::{AuthConfig, GlobalConfig};
+use crate::error::TigerClawError;
+use crate::logger::LogFormat;
+use crate::redact;
+use crate::secret::{SecretRef, SecretResolver};
+use crate::timeouts::{self, ServiceTimeouts};
+use crate::token::{CachedToken, TokenManager};
 use clap::Parser;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
-use std::env;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Environment {
     Local,
     Dev,
@@ -10,61 +18,169 @@ pub enum Environment {
     Production,
 }
 
+impl Environment {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "local" => Some(Environment::Local),
+            "dev" => Some(Environment::Dev),
+            "staging" => Some(Environment::Staging),
+            "production" | "prod" => Some(Environment::Production),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Environment::Local => "local",
+            Environment::Dev => "dev",
+            Environment::Staging => "staging",
+            Environment::Production => "production",
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
 #[command(name = "TigerClaw")]
 #[command(version, about="CLI wrapper to test team tiger things", long_about = None)]
 pub struct Args {
-    #[arg(short, long, help = "Enable verbose output")]
+    #[arg(
+        short,
+        long,
+        global = true,
+        help = "Log debug output, including every HTTP request and response with headers, body and latency"
+    )]
     pub verbose: bool,
+    #[arg(
+        short,
+        long,
+        global = true,
+        conflicts_with = "verbose",
+        help = "Only log warnings and errors"
+    )]
+    pub quiet: bool,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogFormat::Text,
+        help = "Log line format, json writes one object per line for CI"
+    )]
+    pub log_format: LogFormat,
     #[arg(
         short = 'n',
         long,
-        help = "Environment to run against (dev, staging, production)"
+        global = true,
+        visible_alias = "env",
+        help = "Environment to run against (local, dev, staging, production), required by everything but config init"
     )]
-    pub environmnet: String,
-    #[arg(short, long, help = "Advertiser ID to test with")]
+    pub environmnet: Option<String>,
+    #[arg(short, long, global = true, help = "Advertiser ID to test with")]
     pub advertiser_id: Option<i32>,
-    #[arg(short, long, help = "External ID to test with")]
+    #[arg(short, long, global = true, help = "External ID to test with")]
     pub external_id: Option<i32>,
     #[arg(
         short = 's',
         long = "step",
-        help = "Specify the migration orchestration step to execute"
+        global = true,
+        help = "Orchestration steps to execute instead of orchestration.step_to_run, e.g. VALID, SF,ADV or VALID..FEE"
     )]
     pub migration_step: Option<String>,
     #[arg(
         short = 'c',
         long = "config",
+        global = true,
         help = "Path to TOML configuration file specifying which tests to run"
     )]
     pub toml_config: Option<String>,
+    #[arg(long, global = true, help = "Only perform read operations")]
+    pub read_only: bool,
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "read_only",
+        help = "Allow execute-step, force status, locks and deletes outside local and dev, production also asks for a typed confirmation"
+    )]
+    pub allow_mutations: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Send only read operations and print every mutating call instead of sending it"
+    )]
+    pub dry_run: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 
 impl Args {
     pub fn dev() -> Self {
         Args {
             verbose: true,
-            environmnet: "dev".to_string(),
+            quiet: false,
+            log_format: LogFormat::Text,
+            environmnet: Some("dev".to_string()),
             advertiser_id: Some(424242),
             external_id: Some(44911),
             migration_step: None,
             toml_config: None,
+            read_only: false,
+            allow_mutations: false,
+            dry_run: false,
+            command: None,
         }
     }
 
+    /// The environment passed with `-n`. It isn't required by clap so
+    /// `config init --env dev` reads naturally, every other command needs it
+    pub fn environment(&self) -> Result<Environment, TigerClawError> {
+        let name = self.environmnet.as_deref().ok_or_else(|| {
+            TigerClawError::Config(
+                "No environment given, pass -n with local, dev, staging or production".to_string(),
+            )
+        })?;
+        Environment::from_name(name)
+            .ok_or_else(|| TigerClawError::Config(format!("Invalid environment: {}", name)))
+    }
+
+    /// The `-c` file, or the environment's own config. Without a valid `-n`
+    /// only the base `tests.toml` is loaded
     pub fn get_config_path(&self) -> String {
         self.toml_config.clone().unwrap_or_else(|| {
-            match self.environmnet.as_str() {
-                "dev" => "tests.dev.toml",
-                "staging" => "tests.staging.toml",
-                "production" => "tests.prod.toml",
-                _ => "tests.staging.toml",
+            match self.environment() {
+                Ok(Environment::Local) => "tests.local.toml",
+                Ok(Environment::Dev) => "tests.dev.toml",
+                Ok(Environment::Staging) => "tests.staging.toml",
+                Ok(Environment::Production) => "tests.prod.toml",
+                Err(_) => "tests.toml",
             }
             .to_string()
         })
     }
 }
 
+/// Refuses a config written for another environment than `-n`. The
+/// guardrails follow `-n`, so a staging config run with `-n local` would
+/// otherwise reach staging with local's protection
+pub fn check_config_environment(
+    environment: Environment,
+    globals: &GlobalConfig,
+) -> Result<(), TigerClawError> {
+    match Environment::from_name(&globals.environment) {
+        Some(configured) if configured == environment => Ok(()),
+        Some(configured) => Err(TigerClawError::Config(format!(
+            "The config is for {} but -n is {}, pass -n {} or a {} config",
+            configured.name(),
+            environment.name(),
+            configured.name(),
+            environment.name()
+        ))),
+        None => Err(TigerClawError::Config(format!(
+            "globals.environment isn't set in the config, set it to {} to run with -n {}",
+            environment.name(),
+            environment.name()
+        ))),
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TokenRetrievalBody {
     access_token: String,
@@ -73,104 +189,257 @@ pub struct TokenRetrievalBody {
     scope: String,
 }
 
-pub async fn get_token_and_environment(args: &Args) -> (String, Environment) {
-    let environment: Environment;
-    let token_variable_name: String;
+/// Where and as whom client-credentials tokens are requested for an environment
+#[derive(Debug, Clone)]
+pub struct IdpSettings {
+    pub url: String,
+    pub client_id: String,
+    pub client_secret: SecretRef,
+}
 
-    match args.environmnet.as_str() {
-        "local" => {
-            environment = Environment::Local;
-            token_variable_name = "AWIN_SPRINGFIELD_DEV_CLIENT_SECRET".to_string();
-        }
-        "dev" => {
-            environment = Environment::Dev;
-            token_variable_name = "AWIN_SPRINGFIELD_DEV_CLIENT_SECRET".to_string();
-        }
-        "staging" => {
-            environment = Environment::Staging;
-            token_variable_name = "AWIN_SPRINGFIELD_STAGING_CLIENT_SECRET".to_string();
-        }
-        "production" => {
-            environment = Environment::Production;
-            token_variable_name = "AWIN_SPRINGFIELD_PRODUCTION_CLIENT_SECRET".to_string();
-        }
-        _ => panic!("Invalid environment"),
-    };
+impl IdpSettings {
+    /// Builds the IdP settings for an environment, preferring values from the
+    /// `[auth]` config section. Only local and dev have built in defaults,
+    /// staging and production must configure `idp_url` explicitly. The client
+    /// secret comes from `client_secret`, then `client_secret_env`, then the
+    /// environment's `AWIN_SPRINGFIELD_*_CLIENT_SECRET` variable
+    pub fn resolve(environment: &Environment, auth_config: &AuthConfig) -> Result<Self, String> {
+        let default_url = match environment {
+            Environment::Local | Environment::Dev => {
+                Some("http://ui.d-lhr1-docker-026.dev.awin.com/idpbackend/token")
+            }
+            Environment::Staging | Environment::Production => None,
+        };
+        let url = auth_config
+            .idp_url
+            .clone()
+            .or(default_url.map(str::to_string))
+            .ok_or_else(|| {
+                format!(
+                    "No IdP URL configured for {}, set idp_url in the [auth] section",
+                    environment.name()
+                )
+            })?;
+
+        Ok(IdpSettings {
+            url,
+            client_id: auth_config
+                .client_id
+                .clone()
+                .unwrap_or_else(|| "migrationTestClient".to_string()),
+            client_secret: auth_config.client_secret.clone().unwrap_or_else(|| {
+                auth_config
+                    .client_secret_env
+                    .clone()
+                    .map(SecretRef::Env)
+                    .unwrap_or_else(|| Self::default_client_secret(*environment))
+            }),
+        })
+    }
 
-    let token = get_token(&token_variable_name, &environment).await;
-    (token, environment)
+    /// The environment variable the client secret is read from when the
+    /// config doesn't say
+    pub fn default_client_secret(environment: Environment) -> SecretRef {
+        let name = match environment {
+            Environment::Local | Environment::Dev => "AWIN_SPRINGFIELD_DEV_CLIENT_SECRET",
+            Environment::Staging => "AWIN_SPRINGFIELD_STAGING_CLIENT_SECRET",
+            Environment::Production => "AWIN_SPRINGFIELD_PRODUCTION_CLIENT_SECRET",
+        };
+        SecretRef::Env(name.to_string())
+    }
+}
+
+/// Resolves the environment from the CLI args and builds the token manager for
+/// it. No token is requested until a service actually needs one, and the IdP
+/// settings are only required then. What the run may change is up to
+/// `Guardrails`
+pub fn get_token_and_environment(
+    args: &Args,
+    auth_config: &AuthConfig,
+) -> Result<(TokenManager, Environment), TigerClawError> {
+    let environment = args.environment()?;
+    let tokens = TokenManager::for_environment(environment, auth_config);
+    Ok((tokens, environment))
 }
 
-async fn get_token(token_variable_name: &str, enviornmnet: &Environment) -> String {
-    println!(
+/// Requests a new client-credentials token from the IdP, returning `None` if the
+/// secret isn't set, the IdP is unreachable or rejected the request, or the
+/// response could not be parsed. Requests then go out without a token and the
+/// service's 401 surfaces as an auth failure
+pub(crate) async fn get_token(
+    idp: &IdpSettings,
+    enviornmnet: &Environment,
+    secrets: &SecretResolver,
+) -> Option<CachedToken> {
+    info!(
         "attempting to retrieve token:{} for environment: {:?}",
-        token_variable_name, enviornmnet
+        idp.client_secret, enviornmnet
     );
-    match env::var(token_variable_name) {
+    match secrets.resolve(&idp.client_secret).await {
         Ok(client_secret) => {
-            println!(
-                "Found token variable: {} client_secret:{}",
-                token_variable_name, client_secret
-            );
-            let client = reqwest::Client::new();
-            if client_secret.is_empty() {
-                panic!("Client secret not found");
-            }
+            info!("Found token variable: {}", idp.client_secret);
+            // The IdP sits behind the same tunnels as the services
+            let client = match timeouts::http_client(ServiceTimeouts::default()) {
+                Ok(client) => client,
+                Err(error) => {
+                    warn!("{}", error);
+                    return None;
+                }
+            };
 
             let params = [
                 ("grant_type", "client_credentials"),
-                ("client_id", "migrationTestClient"),
+                ("client_id", idp.client_id.as_str()),
                 ("client_secret", &client_secret),
             ];
 
-            let url = match enviornmnet {
-                Environment::Local => "http://ui.d-lhr1-docker-026.dev.awin.com/idpbackend/token",
-                Environment::Dev => "http://ui.d-lhr1-docker-026.dev.awin.com/idpbackend/token",
-                // Staging and production should remain unimplemented for now and should
-                // use the official migration UI instead
-                Environment::Staging => unimplemented!(),
-                Environment::Production => unimplemented!(),
-            };
-
             // Make the post and parse the token out.
             // if we fail to retrieve the token we should fail gracefully
             // as we do not need this same token for sas data import API calls
-            match client.post(url).form(&params).send().await {
+            match client.post(&idp.url).form(&params).send().await {
                 Ok(response) => {
                     if !response.status().is_success() {
-                        println!(
-                            "Error: Failed to retrieve token, status code: {}, response body: {:?}",
+                        warn!(
+                            "Failed to retrieve token, status code: {}, response body: {:?}",
                             response.status(),
                             response
                                 .text()
                                 .await
                                 .unwrap_or_else(|_| "Failed to read response body".to_string())
                         );
-                        return "".to_string();
+                        return None;
                     }
                     let body = response.json::<TokenRetrievalBody>().await;
                     match body {
                         Ok(body) => {
-                            let return_token = body.access_token;
-                            println!(
-                                "Retrieved token for environment {:?}: {}",
-                                enviornmnet, return_token
+                            redact::register_secret(&body.access_token);
+                            info!(
+                                "Retrieved token for environment {:?}, expires in {}s",
+                                enviornmnet, body.expires_in
                             );
-                            return_token
+                            Some(CachedToken::new(body.access_token, body.expires_in))
                         }
                         Err(error) => {
-                            println!("could not extract access token Error: {:?}", error);
-                            "".to_string()
+                            warn!("could not extract access token: {:?}", error);
+                            None
                         }
                     }
                 }
+                Err(error) if error.is_timeout() => {
+                    warn!("TIMED OUT: no response from the IdP at {}", idp.url);
+                    None
+                }
                 Err(error) => {
-                    panic!("Error: {:?}", error);
+                    warn!("Failed to reach the IdP at {}: {}", idp.url, error);
+                    None
                 }
             }
         }
         Err(err) => {
-            panic!("client secret Env variable not extracted, error: {:?}", err)
+            warn!(
+                "Client secret {} could not be read: {}",
+                idp.client_secret, err
+            );
+            None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::LocalParameterStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn configs_for_another_environment_are_refused() {
+        let globals = |environment: &str| GlobalConfig {
+            environment: environment.to_string(),
+            ..GlobalConfig::default()
+        };
+        assert!(check_config_environment(Environment::Production, &globals("prod")).is_ok());
+        let error = check_config_environment(Environment::Local, &globals("staging")).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("The config is for staging but -n is local"),
+            "{}",
+            error
+        );
+        assert!(check_config_environment(Environment::Dev, &globals("")).is_err());
+
+        let args = Args {
+            environmnet: Some("local".to_string()),
+            ..Args::dev()
+        };
+        assert_eq!(args.get_config_path(), "tests.local.toml");
+    }
+
+    #[test]
+    fn staging_requires_configured_idp_url() {
+        let result = IdpSettings::resolve(&Environment::Staging, &AuthConfig::default());
+        assert!(result.is_err());
+
+        let auth_config = AuthConfig {
+            idp_url: Some("https://idp.staging.example.com/token".to_string()),
+            client_id: Some("stagingClient".to_string()),
+            ..AuthConfig::default()
+        };
+        let idp = IdpSettings::resolve(&Environment::Staging, &auth_config).unwrap();
+        assert_eq!(idp.url, "https://idp.staging.example.com/token");
+        assert_eq!(idp.client_id, "stagingClient");
+        assert_eq!(
+            idp.client_secret,
+            SecretRef::Env("AWIN_SPRINGFIELD_STAGING_CLIENT_SECRET".to_string())
+        );
+    }
+
+    #[test]
+    fn production_requires_configured_idp_url() {
+        let mut args = Args::dev();
+        args.environmnet = Some("production".to_string());
+        let (tokens, _) = get_token_and_environment(&args, &AuthConfig::default()).unwrap();
+        assert!(tokens.idp().is_err());
+
+        let auth_config = AuthConfig {
+            idp_url: Some("https://idp.example.com/token".to_string()),
+            ..AuthConfig::default()
+        };
+        let (tokens, environment) = get_token_and_environment(&args, &auth_config).unwrap();
+        assert_eq!(environment, Environment::Production);
+        assert_eq!(tokens.idp().unwrap().url, "https://idp.example.com/token");
+    }
+
+    #[tokio::test]
+    async fn token_is_requested_from_configured_idp() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/token")
+            .match_body(mockito::Matcher::UrlEncoded(
+                "client_id".to_string(),
+                "stagingClient".to_string(),
+            ))
+            .with_status(200)
+            .with_body(
+                r#"{"access_token":"staging-token","expires_in":300,"token_type":"Bearer","scope":"migration"}"#,
+            )
+            .create_async()
+            .await;
+
+        let secrets = SecretResolver::new(Arc::new(LocalParameterStore::new([(
+            "/tiger-claw/staging/idp-secret",
+            "secret",
+        )])));
+        let idp = IdpSettings {
+            url: format!("{}/token", server.url()),
+            client_id: "stagingClient".to_string(),
+            client_secret: SecretRef::AwsSsm("/tiger-claw/staging/idp-secret".to_string()),
+        };
+
+        let token = get_token(&idp, &Environment::Staging, &secrets)
+            .await
+            .unwrap();
+        assert_eq!(token.access_token, "staging-token");
+        mock.assert_async().await;
+    }
+}
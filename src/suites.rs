@@ -0,0 +1,610 @@
+use crate::config::{SuiteConfig, TestConfig};
+use crate::config_check::Diagnostic;
+use crate::error::TigerClawError;
+use crate::health::{Service, ServiceHealth};
+use crate::logger::LogGroup;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use tokio::time::Instant;
+
+/// A group of checks the binary runs when no subcommand is given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Suite {
+    Orchestration,
+    SasDataImport,
+    Terms,
+    DynamoDb,
+    MigrationApi,
+}
+
+impl Suite {
+    /// Every suite, in the order they run unless `[suites]` says otherwise
+    pub const ALL: [Suite; 5] = [
+        Suite::Orchestration,
+        Suite::SasDataImport,
+        Suite::Terms,
+        Suite::DynamoDb,
+        Suite::MigrationApi,
+    ];
+
+    /// Name of the suite's config section, also used in `[suites]`
+    pub fn name(self) -> &'static str {
+        match self {
+            Suite::Orchestration => "orchestration",
+            Suite::SasDataImport => "sas_data_import",
+            Suite::Terms => "terms",
+            Suite::DynamoDb => "dynamo_db",
+            Suite::MigrationApi => "migration_api",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Suite> {
+        Suite::ALL.into_iter().find(|suite| suite.name() == name)
+    }
+
+    /// Services the suite can't do anything useful without. Orchestration
+    /// diffs the DynamoDB record around every step, the migration API reads
+    /// the merchant back to verify its locks
+    pub fn services(self) -> &'static [Service] {
+        match self {
+            Suite::Orchestration => &[Service::GrowthMigration, Service::DynamoDb],
+            Suite::SasDataImport => &[Service::SasDataImport],
+            Suite::Terms => &[Service::GrowthMigration],
+            Suite::DynamoDb => &[Service::DynamoDb],
+            Suite::MigrationApi => &[Service::GrowthMigration, Service::SasDataImport],
+        }
+    }
+
+    /// Whether the config turns the suite on. Orchestration without steps to
+    /// run is a config problem, not a reason to skip it
+    pub fn enabled(self, config: &TestConfig) -> bool {
+        match self {
+            Suite::Orchestration => config.orchestration.enabled,
+            Suite::SasDataImport => config.sas_data_import.enabled,
+            Suite::Terms => config.terms.enabled,
+            Suite::DynamoDb => config.dynamo_db.enabled,
+            Suite::MigrationApi => config.migration_api.enabled,
+        }
+    }
+}
+
+/// How a suite went
+#[derive(Debug)]
+pub enum SuiteOutcome {
+    Passed,
+    Failed(TigerClawError),
+    /// Not run, with the reason
+    Skipped(String),
+}
+
+impl From<Result<(), TigerClawError>> for SuiteOutcome {
+    fn from(result: Result<(), TigerClawError>) -> Self {
+        match result {
+            Ok(()) => SuiteOutcome::Passed,
+            Err(error) => SuiteOutcome::Failed(error),
+        }
+    }
+}
+
+/// The order the suites run in and what each depends on, worked out from
+/// `[suites]` once at startup. A suite always runs after its dependencies,
+/// otherwise in the order `suites.order` lists them
+#[derive(Debug, Clone)]
+pub struct SuitePlan {
+    order: Vec<Suite>,
+    depends_on: HashMap<Suite, Vec<Suite>>,
+    concurrency: usize,
+}
+
+impl SuitePlan {
+    /// Fails with every unknown suite name and dependency cycle found
+    pub fn new(config: &SuiteConfig) -> Result<Self, Vec<Diagnostic>> {
+        let mut problems = Vec::new();
+        if config.concurrency == 0 {
+            problems.push(Diagnostic::for_key(
+                "suites.concurrency",
+                "suites.concurrency must be at least 1",
+            ));
+        }
+        let mut preferred = Vec::new();
+        for name in &config.order {
+            match Suite::from_name(name) {
+                Some(suite) if preferred.contains(&suite) => problems.push(Diagnostic::for_key(
+                    "suites.order",
+                    format!("{} is listed more than once in suites.order", name),
+                )),
+                Some(suite) => preferred.push(suite),
+                None => problems.push(Diagnostic::for_key("suites.order", unknown(name))),
+            }
+        }
+        // Suites left out of `order` run after the listed ones
+        for suite in Suite::ALL {
+            if !preferred.contains(&suite) {
+                preferred.push(suite);
+            }
+        }
+
+        let mut depends_on: HashMap<Suite, Vec<Suite>> = HashMap::new();
+        for (name, dependencies) in &config.depends_on {
+            let key = format!("suites.depends_on.{}", name);
+            let Some(suite) = Suite::from_name(name) else {
+                problems.push(Diagnostic::for_key(&key, unknown(name)));
+                continue;
+            };
+            for dependency in dependencies {
+                match Suite::from_name(dependency) {
+                    Some(dependency) if dependency == suite => problems.push(Diagnostic::for_key(
+                        &key,
+                        format!("{} can't depend on itself", name),
+                    )),
+                    Some(dependency) => depends_on.entry(suite).or_default().push(dependency),
+                    None => problems.push(Diagnostic::for_key(&key, unknown(dependency))),
+                }
+            }
+        }
+
+        // Takes the first suite in `preferred` whose dependencies are all
+        // placed, until none is left or the rest depend on each other
+        let mut order = Vec::new();
+        while order.len() < preferred.len() {
+            let next = preferred.iter().find(|suite| {
+                !order.contains(*suite)
+                    && depends_on
+                        .get(*suite)
+                        .is_none_or(|dependencies| dependencies.iter().all(|d| order.contains(d)))
+            });
+            match next {
+                Some(suite) => order.push(*suite),
+                None => {
+                    let cycle: Vec<_> = preferred
+                        .iter()
+                        .filter(|suite| !order.contains(*suite))
+                        .map(|suite| suite.name())
+                        .collect();
+                    problems.push(Diagnostic::for_key(
+                        "suites.depends_on",
+                        format!("Suites {} depend on each other", cycle.join(", ")),
+                    ));
+                    break;
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(SuitePlan {
+                order,
+                depends_on,
+                concurrency: config.concurrency,
+            })
+        } else {
+            Err(problems)
+        }
+    }
+
+    pub fn order(&self) -> &[Suite] {
+        &self.order
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    pub fn depends_on(&self, suite: Suite) -> &[Suite] {
+        self.depends_on.get(&suite).map_or(&[], Vec::as_slice)
+    }
+
+    /// Why `suite` can't run given how the suites before it went, if it can't.
+    /// Every dependency has to have passed
+    pub fn blocked(&self, suite: Suite, report: &SuiteReport) -> Option<String> {
+        self.depends_on(suite)
+            .iter()
+            .find_map(|dependency| match report.outcome(*dependency) {
+                Some(SuiteOutcome::Passed) => None,
+                Some(SuiteOutcome::Failed(_)) => {
+                    Some(format!("depends on {}, which failed", dependency.name()))
+                }
+                Some(SuiteOutcome::Skipped(reason)) => Some(format!(
+                    "depends on {}, which was skipped: {}",
+                    dependency.name(),
+                    reason
+                )),
+                None => Some(format!(
+                    "depends on {}, which didn't run",
+                    dependency.name()
+                )),
+            })
+    }
+}
+
+/// Runs every suite of the plan with `run_suite`, starting each once its
+/// dependencies have finished and no more than `concurrency` at a time.
+/// Suites that aren't enabled, whose dependencies didn't pass or that need a
+/// service `health` found unavailable are skipped. When suites run at once
+/// each one's output is grouped, see `LogGroup`. A suite still running at
+/// `deadline` fails as timed out with its output so far, the ones not
+/// started yet are skipped
+pub async fn run<F, Fut>(
+    plan: &SuitePlan,
+    config: &TestConfig,
+    health: &ServiceHealth,
+    deadline: Option<Instant>,
+    run_suite: F,
+) -> SuiteReport
+where
+    F: Fn(Suite) -> Fut,
+    Fut: Future<Output = Result<(), TigerClawError>>,
+{
+    let grouped = plan.concurrency > 1;
+    let mut report = SuiteReport::default();
+    let mut pending = plan.order.clone();
+    let mut running = FuturesUnordered::new();
+    loop {
+        // Dependencies come first in the plan, so skipping a suite can only
+        // unblock the ones after it in this same pass
+        let mut index = 0;
+        while index < pending.len() {
+            let suite = pending[index];
+            let ready = plan
+                .depends_on(suite)
+                .iter()
+                .all(|dependency| report.outcome(*dependency).is_some());
+            if !ready {
+                index += 1;
+                continue;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                warn!("Skipping {}: the run deadline has passed", suite.name());
+                report.record(
+                    suite,
+                    SuiteOutcome::Skipped("the run deadline has passed".to_string()),
+                );
+            } else if !suite.enabled(config) {
+                info!(
+                    "{} is not enabled in the configuration, skipping.",
+                    suite.name()
+                );
+                report.record(suite, SuiteOutcome::Skipped("not enabled".to_string()));
+            } else if let Some(reason) = plan
+                .blocked(suite, &report)
+                .or_else(|| health.blocked(suite))
+            {
+                warn!("Skipping {}: {}", suite.name(), reason);
+                report.record(suite, SuiteOutcome::Skipped(reason));
+            } else if running.len() < plan.concurrency {
+                info!("{} is enabled, running...", suite.name());
+                let group = grouped.then(|| LogGroup::new(suite.name()));
+                let result = within(deadline, suite, run_suite(suite));
+                running.push(async move {
+                    let result = match &group {
+                        Some(group) => group.scope(result).await,
+                        None => result.await,
+                    };
+                    if let Some(group) = group {
+                        group.flush();
+                    }
+                    (suite, result)
+                });
+            } else {
+                index += 1;
+                continue;
+            }
+            pending.remove(index);
+        }
+
+        match running.next().await {
+            Some((suite, result)) => report.record(suite, SuiteOutcome::from(result)),
+            None => break,
+        }
+    }
+    report
+}
+
+/// `result`, or a timed out error logged from inside the suite's group when
+/// `deadline` comes first
+async fn within<Fut>(
+    deadline: Option<Instant>,
+    suite: Suite,
+    result: Fut,
+) -> Result<(), TigerClawError>
+where
+    Fut: Future<Output = Result<(), TigerClawError>>,
+{
+    let Some(deadline) = deadline else {
+        return result.await;
+    };
+    tokio::time::timeout_at(deadline, result)
+        .await
+        .unwrap_or_else(|_| {
+            let error = TigerClawError::TimedOut(format!(
+                "{} to finish before the run deadline",
+                suite.name()
+            ));
+            error!("{}", error);
+            Err(error)
+        })
+}
+
+fn unknown(name: &str) -> String {
+    let names: Vec<_> = Suite::ALL.iter().map(|suite| suite.name()).collect();
+    format!(
+        "Unknown suite: {}. Must be one of {}",
+        name,
+        names.join(", ")
+    )
+}
+
+/// Outcome of every suite of a run, in the order they finished
+#[derive(Debug, Default)]
+pub struct SuiteReport {
+    outcomes: Vec<(Suite, SuiteOutcome)>,
+}
+
+impl SuiteReport {
+    pub fn record(&mut self, suite: Suite, outcome: SuiteOutcome) {
+        self.outcomes.push((suite, outcome));
+    }
+
+    pub fn outcome(&self, suite: Suite) -> Option<&SuiteOutcome> {
+        self.outcomes
+            .iter()
+            .find(|(recorded, _)| *recorded == suite)
+            .map(|(_, outcome)| outcome)
+    }
+
+    /// One line per suite, for the end of the run
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec!["Suite results:".to_string()];
+        lines.extend(self.outcomes.iter().map(|(suite, outcome)| match outcome {
+            SuiteOutcome::Passed => format!("  {}: passed", suite.name()),
+            SuiteOutcome::Failed(error) => format!("  {}: failed, {}", suite.name(), error),
+            SuiteOutcome::Skipped(reason) => format!("  {}: skipped, {}", suite.name(), reason),
+        }));
+        lines
+    }
+
+    /// The first failure, skipped suites don't fail the run
+    pub fn into_result(self) -> Result<(), TigerClawError> {
+        self.outcomes
+            .into_iter()
+            .find_map(|(_, outcome)| match outcome {
+                SuiteOutcome::Failed(error) => Some(error),
+                _ => None,
+            })
+            .map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suites(toml: &str) -> SuiteConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn dependencies_run_first_and_order_decides_the_rest() {
+        let plan = SuitePlan::new(&SuiteConfig::default()).unwrap();
+        assert_eq!(plan.order(), Suite::ALL);
+
+        let plan = SuitePlan::new(&suites(
+            "order = [\"migration_api\", \"sas_data_import\"]\n[depends_on]\nmigration_api = [\"orchestration\"]\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            plan.order(),
+            [
+                Suite::SasDataImport,
+                Suite::Orchestration,
+                Suite::MigrationApi,
+                Suite::Terms,
+                Suite::DynamoDb,
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_suites_and_cycles_are_reported() {
+        let problems = SuitePlan::new(&suites(
+            "order = [\"widgets\"]\n[depends_on]\norchestration = [\"migration_api\"]\nmigration_api = [\"orchestration\"]\n",
+        ))
+        .unwrap_err();
+        let messages: Vec<_> = problems.iter().map(|p| p.message.as_str()).collect();
+        assert_eq!(problems.len(), 2, "{:?}", messages);
+        assert!(messages[0].starts_with("Unknown suite: widgets"));
+        assert_eq!(
+            messages[1],
+            "Suites orchestration, migration_api depend on each other"
+        );
+    }
+
+    fn all_enabled() -> TestConfig {
+        let mut config = TestConfig::default();
+        config.orchestration.enabled = true;
+        config.orchestration.step_to_run = "VALID".to_string();
+        config.sas_data_import.enabled = true;
+        config.migration_api.enabled = true;
+        config
+    }
+
+    #[tokio::test]
+    async fn independent_suites_run_at_once_up_to_the_limit() {
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        let config = all_enabled();
+        let events = Mutex::new(Vec::new());
+        let plan = SuitePlan::new(&suites(
+            "concurrency = 2\n[depends_on]\nmigration_api = [\"orchestration\"]\n",
+        ))
+        .unwrap();
+        let report = run(&plan, &config, &ServiceHealth::default(), None, |suite| {
+            let events = &events;
+            async move {
+                events
+                    .lock()
+                    .unwrap()
+                    .push(format!("start {}", suite.name()));
+                let millis = if suite == Suite::Orchestration { 50 } else { 1 };
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                events.lock().unwrap().push(format!("end {}", suite.name()));
+                Ok(())
+            }
+        })
+        .await;
+
+        assert_eq!(
+            events.into_inner().unwrap(),
+            [
+                "start orchestration",
+                "start sas_data_import",
+                "end sas_data_import",
+                "end orchestration",
+                "start migration_api",
+                "end migration_api",
+            ]
+        );
+        assert!(report.into_result().is_ok());
+    }
+
+    #[tokio::test]
+    async fn terms_and_dynamo_db_run_alongside_sas_data_import() {
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        let mut config = TestConfig::default();
+        config.sas_data_import.enabled = true;
+        config.terms.enabled = true;
+        config.dynamo_db.enabled = true;
+        let events = Mutex::new(Vec::new());
+        let plan = SuitePlan::new(&suites("concurrency = 3\n")).unwrap();
+        let report = run(&plan, &config, &ServiceHealth::default(), None, |suite| {
+            let events = &events;
+            async move {
+                events
+                    .lock()
+                    .unwrap()
+                    .push(format!("start {}", suite.name()));
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                events.lock().unwrap().push(format!("end {}", suite.name()));
+                Ok(())
+            }
+        })
+        .await;
+
+        let events = events.into_inner().unwrap();
+        assert_eq!(
+            events[..3],
+            ["start sas_data_import", "start terms", "start dynamo_db"]
+        );
+        assert_eq!(events.len(), 6);
+        assert!(report.into_result().is_ok());
+    }
+
+    #[tokio::test]
+    async fn suites_running_at_the_deadline_time_out_and_the_rest_are_skipped() {
+        use std::time::Duration;
+
+        let config = all_enabled();
+        let plan = SuitePlan::new(&suites(
+            "concurrency = 2\n[depends_on]\nmigration_api = [\"sas_data_import\"]\n",
+        ))
+        .unwrap();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let report = run(
+            &plan,
+            &config,
+            &ServiceHealth::default(),
+            Some(deadline),
+            |suite| async move {
+                let millis = if suite == Suite::Orchestration {
+                    10
+                } else {
+                    5_000
+                };
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                Ok(())
+            },
+        )
+        .await;
+
+        assert!(Instant::now() < deadline + Duration::from_secs(1));
+        assert!(matches!(
+            report.outcome(Suite::Orchestration),
+            Some(SuiteOutcome::Passed)
+        ));
+        assert!(matches!(
+            report.outcome(Suite::SasDataImport),
+            Some(SuiteOutcome::Failed(TigerClawError::TimedOut(_)))
+        ));
+        assert!(matches!(
+            report.outcome(Suite::MigrationApi),
+            Some(SuiteOutcome::Skipped(_))
+        ));
+        assert!(report.into_result().unwrap_err().is_timeout());
+    }
+
+    #[tokio::test]
+    async fn one_at_a_time_keeps_the_plan_order() {
+        let config = all_enabled();
+        let plan = SuitePlan::new(&suites(
+            "order = [\"migration_api\", \"sas_data_import\", \"orchestration\"]\n[depends_on]\nmigration_api = [\"orchestration\"]\n",
+        ))
+        .unwrap();
+        let report = run(
+            &plan,
+            &config,
+            &ServiceHealth::default(),
+            None,
+            |suite| async move {
+                match suite {
+                    Suite::Orchestration => Err(TigerClawError::Assertion("step".to_string())),
+                    _ => Ok(()),
+                }
+            },
+        )
+        .await;
+        let summary = report.summary();
+        assert_eq!(
+            summary[1..],
+            [
+                "  terms: skipped, not enabled",
+                "  dynamo_db: skipped, not enabled",
+                "  sas_data_import: passed",
+                "  orchestration: failed, Check failed: step",
+                "  migration_api: skipped, depends on orchestration, which failed",
+            ]
+        );
+    }
+
+    #[test]
+    fn suites_are_skipped_unless_every_dependency_passed() {
+        let plan = SuitePlan::new(&suites(
+            "[depends_on]\nmigration_api = [\"orchestration\", \"sas_data_import\"]\n",
+        ))
+        .unwrap();
+        let mut report = SuiteReport::default();
+        report.record(Suite::Orchestration, SuiteOutcome::Passed);
+        report.record(
+            Suite::SasDataImport,
+            SuiteOutcome::Skipped("not enabled".to_string()),
+        );
+        assert_eq!(
+            plan.blocked(Suite::MigrationApi, &report).as_deref(),
+            Some("depends on sas_data_import, which was skipped: not enabled")
+        );
+        assert_eq!(plan.blocked(Suite::Orchestration, &report), None);
+
+        report.record(
+            Suite::MigrationApi,
+            SuiteOutcome::Failed(TigerClawError::Assertion("locks".to_string())),
+        );
+        assert_eq!(report.summary().len(), 4);
+        assert!(matches!(
+            report.into_result(),
+            Err(TigerClawError::Assertion(_))
+        ));
+    }
+}
@@ -1,5 +1,6 @@
-use crate::request::growth_migration_get;
-use crate::config::TestConfig;
+use crate::error::TigerClawError;
+use crate::growth_migration::GrowthMigrationClient;
+use log::{error, info};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -36,36 +37,41 @@ impl TermParams {
     }
 }
 
-pub async fn run(jwt_token: &str, advertiser_id: &i32, test_config: &TestConfig) {
-    get_advertiser_terms_status(jwt_token, advertiser_id, test_config).await;
-    println!("completed terms and conditions test, no failures");
+pub async fn run(
+    client: &GrowthMigrationClient,
+    advertiser_id: &i32,
+) -> Result<(), TigerClawError> {
+    get_advertiser_terms_status(client, advertiser_id).await?;
+    info!("completed terms and conditions test, no failures");
+    Ok(())
 }
 
-async fn get_advertiser_terms_status(jwt_token: &str, advertiser_id: &i32, test_config: &TestConfig) {
-    let terms_endpoint = format!("terms/sas/advertiser/awin/{}", advertiser_id);
-    let response = growth_migration_get(&terms_endpoint, jwt_token, test_config)
-        .await
-        .expect("Failed to get endpoint");
-
-    match response.status() {
-        reqwest::StatusCode::OK => match response.json::<Terms>().await {
-            Ok(terms) => {
-                assert!(terms.term_params.external_program_id > 0);
-                assert!(!terms.term_params.external_program_name.is_empty());
-                println!("Advertiser terms: {:?}", terms);
-            }
-            Err(error) => {
-                panic!("Failed to parse response: {:?}", error);
+async fn get_advertiser_terms_status(
+    client: &GrowthMigrationClient,
+    advertiser_id: &i32,
+) -> Result<(), TigerClawError> {
+    match client.terms(*advertiser_id).await {
+        Ok(terms) => {
+            info!("Advertiser terms: {:?}", terms);
+            let errors = terms.term_params.validate();
+            if !errors.is_empty() {
+                return Err(TigerClawError::Assertion(format!(
+                    "Terms for advertiser {} are invalid: {}",
+                    advertiser_id,
+                    errors.join(", ")
+                )));
             }
-        },
-        reqwest::StatusCode::NOT_FOUND => {
-            panic!("Advertiser not found or terms not available")
+            Ok(())
         }
-        reqwest::StatusCode::UNAUTHORIZED => {
-            panic!("Unauthorized access: Invalid JWT token");
+        Err(error) if error.status() == Some(reqwest::StatusCode::NOT_FOUND) => {
+            Err(TigerClawError::Assertion(format!(
+                "Advertiser {} not found or terms not available",
+                advertiser_id
+            )))
         }
-        _ => {
-            panic!("Unexpected status code: {:?}", response.status());
+        Err(error) => {
+            error!("Failed to get advertiser terms: {}", error);
+            Err(error)
         }
     }
 }
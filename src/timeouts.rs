@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Timeouts from the optional `[timeouts]` config section, so a hung tunnel
+/// fails the check instead of blocking the run forever
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Time allowed to open a connection
+    pub connect_seconds: u64,
+    /// Time allowed between reads once connected
+    pub read_seconds: u64,
+    /// Deadline for the whole run, unset means no deadline
+    pub run_deadline_seconds: Option<u64>,
+    /// Per service overrides keyed by service name, e.g. `sas_data_import`
+    pub services: HashMap<String, ServiceTimeoutConfig>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            connect_seconds: 10,
+            read_seconds: 60,
+            run_deadline_seconds: None,
+            services: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ServiceTimeoutConfig {
+    pub connect_seconds: Option<u64>,
+    pub read_seconds: Option<u64>,
+}
+
+/// Resolved timeouts for one service
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServiceTimeouts {
+    pub connect: Duration,
+    pub read: Duration,
+}
+
+impl Default for ServiceTimeouts {
+    fn default() -> Self {
+        TimeoutConfig::default().for_service("")
+    }
+}
+
+impl TimeoutConfig {
+    /// The service's overrides, falling back to the section wide values
+    pub fn for_service(&self, service: &str) -> ServiceTimeouts {
+        let overrides = self.services.get(service).cloned().unwrap_or_default();
+        ServiceTimeouts {
+            connect: Duration::from_secs(overrides.connect_seconds.unwrap_or(self.connect_seconds)),
+            read: Duration::from_secs(overrides.read_seconds.unwrap_or(self.read_seconds)),
+        }
+    }
+
+    pub fn run_deadline(&self) -> Option<Duration> {
+        self.run_deadline_seconds.map(Duration::from_secs)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let zero = |seconds: Option<u64>| seconds == Some(0);
+        if self.connect_seconds == 0 || self.read_seconds == 0 || zero(self.run_deadline_seconds) {
+            return Err("timeouts must be greater than 0 seconds".to_string());
+        }
+        for (service, overrides) in &self.services {
+            if zero(overrides.connect_seconds) || zero(overrides.read_seconds) {
+                return Err(format!(
+                    "timeouts for {} must be greater than 0 seconds",
+                    service
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds the HTTP client a service's requests go through
+pub fn http_client(timeouts: ServiceTimeouts) -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .connect_timeout(timeouts.connect)
+        .read_timeout(timeouts.read)
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_overrides_fall_back_to_section_values() {
+        let config: TimeoutConfig = toml::from_str(
+            r#"
+            read_seconds = 30
+            [services.sas_data_import]
+            read_seconds = 120
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.for_service("sas_data_import"),
+            ServiceTimeouts {
+                connect: Duration::from_secs(10),
+                read: Duration::from_secs(120),
+            }
+        );
+        assert_eq!(
+            config.for_service("growth_migration").read,
+            Duration::from_secs(30)
+        );
+        assert_eq!(config.run_deadline(), None);
+    }
+
+    #[test]
+    fn zero_timeouts_are_rejected() {
+        let mut config = TimeoutConfig {
+            run_deadline_seconds: Some(0),
+            ..TimeoutConfig::default()
+        };
+        assert!(config.validate().is_err());
+        config.run_deadline_seconds = Some(600);
+        config.services.insert(
+            "growth_migration".to_string(),
+            ServiceTimeoutConfig {
+                connect_seconds: Some(0),
+                read_seconds: None,
+            },
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn hung_service_times_out() {
+        // Accepts the connection but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let _server = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        });
+
+        let client = http_client(ServiceTimeouts {
+            connect: Duration::from_secs(1),
+            read: Duration::from_millis(200),
+        })
+        .unwrap();
+        let error = client
+            .get(format!("http://{}/", address))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(error.is_timeout());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::config::AuthConfig;
+use crate::redact;
+use crate::secret::SecretResolver;
+use crate::setup::{self, Environment, IdpSettings};
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// An access token along with the moment the IdP says it stops being valid
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedToken {
+    pub access_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl CachedToken {
+    pub fn new(access_token: String, expires_in: i32) -> Self {
+        CachedToken {
+            access_token,
+            expires_at: Utc::now() + Duration::seconds(expires_in as i64),
+        }
+    }
+
+    /// A token is fresh if it will still be valid once `margin` has passed,
+    /// which leaves room for the request it is about to be used for
+    pub fn is_fresh(&self, margin: Duration) -> bool {
+        !self.access_token.is_empty() && Utc::now() + margin < self.expires_at
+    }
+}
+
+/// Hands out client-credentials tokens for an environment, fetching a new one
+/// only when the cached token is missing, close to expiry or has been rejected
+pub struct TokenManager {
+    environment: Environment,
+    idp: Result<IdpSettings, String>,
+    cache_file: Option<PathBuf>,
+    refresh_margin: Duration,
+    secrets: Arc<SecretResolver>,
+    cached: Mutex<Option<CachedToken>>,
+}
+
+impl TokenManager {
+    pub fn new(environment: Environment, idp: IdpSettings, auth_config: &AuthConfig) -> Self {
+        Self::with_idp(environment, Ok(idp), auth_config)
+    }
+
+    /// Token manager for the `[auth]` IdP of `environment`. A missing or
+    /// invalid IdP only fails once a service asks for a client-credentials
+    /// token, see `idp`
+    pub fn for_environment(environment: Environment, auth_config: &AuthConfig) -> Self {
+        let idp = IdpSettings::resolve(&environment, auth_config);
+        Self::with_idp(environment, idp, auth_config)
+    }
+
+    fn with_idp(
+        environment: Environment,
+        idp: Result<IdpSettings, String>,
+        auth_config: &AuthConfig,
+    ) -> Self {
+        let cache_file = if auth_config.cache_token_file {
+            default_cache_file(&environment)
+        } else {
+            None
+        };
+
+        TokenManager {
+            environment,
+            idp,
+            cache_file,
+            refresh_margin: Duration::seconds(auth_config.refresh_margin_seconds),
+            secrets: Arc::new(SecretResolver::default()),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Overrides where the token is cached on disk, mainly so tests don't
+    /// touch the real user cache directory
+    pub fn with_cache_file(mut self, cache_file: Option<PathBuf>) -> Self {
+        self.cache_file = cache_file;
+        self
+    }
+
+    /// Reads the client secret and every other credential of the run through
+    /// `secrets`, e.g. one backed by a local stand-in for SSM
+    pub fn with_secrets(mut self, secrets: Arc<SecretResolver>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    pub fn secrets(&self) -> Arc<SecretResolver> {
+        self.secrets.clone()
+    }
+
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Where tokens are requested, or why they can't be
+    pub fn idp(&self) -> Result<&IdpSettings, String> {
+        self.idp.as_ref().map_err(String::clone)
+    }
+
+    /// Returns a valid access token, or an empty string if one could not be
+    /// retrieved. Services that don't need the token can keep running either way
+    pub async fn token(&self) -> String {
+        let mut cached = self.cached.lock().await;
+        if let Some(token) = cached.as_ref()
+            && token.is_fresh(self.refresh_margin)
+        {
+            return token.access_token.clone();
+        }
+
+        if let Some(token) = self.read_cache_file()
+            && token.is_fresh(self.refresh_margin)
+        {
+            redact::register_secret(&token.access_token);
+            let access_token = token.access_token.clone();
+            *cached = Some(token);
+            return access_token;
+        }
+
+        let idp = match self.idp() {
+            Ok(idp) => idp,
+            Err(error) => {
+                warn!("{}", error);
+                return String::new();
+            }
+        };
+        match setup::get_token(idp, &self.environment, &self.secrets).await {
+            Some(token) => {
+                self.write_cache_file(&token);
+                let access_token = token.access_token.clone();
+                *cached = Some(token);
+                access_token
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Drops the cached token so the next call to `token` fetches a new one,
+    /// used when a service rejects the current token with a 401
+    pub async fn invalidate(&self) {
+        *self.cached.lock().await = None;
+        if let Some(path) = &self.cache_file {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn read_cache_file(&self) -> Option<CachedToken> {
+        let path = self.cache_file.as_ref()?;
+        let contents = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    fn write_cache_file(&self, token: &CachedToken) {
+        let Some(path) = &self.cache_file else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        match serde_json::to_string(token) {
+            Ok(contents) => {
+                if let Err(error) = write_private(path, &contents) {
+                    warn!("Failed to cache token at {}: {}", path.display(), error);
+                }
+            }
+            Err(error) => warn!("Failed to serialise token for caching: {}", error),
+        }
+    }
+}
+
+/// Writes the file readable only by the current user, the token grants API access
+#[cfg(unix)]
+fn write_private(path: &PathBuf, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &PathBuf, contents: &str) -> std::io::Result<()> {
+    fs::write(path, contents)
+}
+
+/// User-local cache location, one file per environment so dev and staging
+/// tokens never get mixed up
+fn default_cache_file(environment: &Environment) -> Option<PathBuf> {
+    let cache_dir = match env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".cache"),
+    };
+    Some(
+        cache_dir
+            .join("tiger-claw")
+            .join(format!("token-{}.json", environment.name())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn token_close_to_expiry_is_not_fresh() {
+        let token = CachedToken::new("abc".to_string(), 30);
+        assert!(token.is_fresh(Duration::seconds(0)));
+        assert!(!token.is_fresh(Duration::seconds(60)));
+    }
+
+    #[test]
+    fn empty_token_is_never_fresh() {
+        let token = CachedToken::new(String::new(), 3600);
+        assert!(!token.is_fresh(Duration::seconds(0)));
+    }
+
+    #[tokio::test]
+    async fn fresh_token_is_read_from_cache_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("token-dev.json");
+        let cached = CachedToken::new("from-disk".to_string(), 3600);
+        fs::write(&path, serde_json::to_string(&cached).unwrap()).unwrap();
+
+        let idp = IdpSettings::resolve(&Environment::Dev, &AuthConfig::default()).unwrap();
+        let tokens = TokenManager::new(Environment::Dev, idp, &AuthConfig::default())
+            .with_cache_file(Some(path.clone()));
+        assert_eq!(tokens.token().await, "from-disk");
+
+        tokens.invalidate().await;
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::Write;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+const CLIENT_SECRET: &str = "redaction-test-client-secret";
+const API_KEY: &str = "redaction-test-sas-api-key";
+const ACCESS_TOKEN: &str = "redaction-test-access-token";
+
+/// Runs the binary against mocked services with known secrets and checks that
+/// none of them make it into stdout or stderr
+#[test]
+fn secrets_never_appear_in_output() {
+    let mut server = mockito::Server::new();
+    let host = server.host_with_port();
+    let port = host.rsplit(':').next().unwrap();
+
+    let _idp = server
+        .mock("POST", "/token")
+        .with_status(200)
+        .with_body(format!(
+            r#"{{"access_token":"{}","expires_in":300,"token_type":"Bearer","scope":"migration"}}"#,
+            ACCESS_TOKEN
+        ))
+        .create();
+    // Echo the credentials back in the response the way a misbehaving service might
+    let _lockdown = server
+        .mock(
+            "POST",
+            mockito::Matcher::Regex("^/sasMigrationApi/.*".to_string()),
+        )
+        .with_status(500)
+        .with_body(format!("Bearer {} {}", ACCESS_TOKEN, CLIENT_SECRET))
+        .create();
+    let _merchant = server
+        .mock("GET", "/merchant/44911")
+        .with_status(500)
+        .with_body(API_KEY)
+        .create();
+
+    let mut config = NamedTempFile::new().unwrap();
+    write!(
+        config,
+        r#"
+[orchestration]
+enabled = false
+step_to_run = ""
+step_status_to_force = ""
+force_run = false
+
+[sas_data_import]
+enabled = true
+
+[dynamo_db]
+enabled = false
+
+[migration_api]
+enabled = true
+
+[globals]
+environment = "local"
+advertiser_id = 424242
+external_id = 44911
+migration_name = "sas"
+base_growth_migration_url = "http://127.0.0.1"
+base_growth_migration_port = {port}
+base_sas_data_import_url = "http://127.0.0.1"
+base_sas_data_import_port = {port}
+
+[auth]
+idp_url = "http://127.0.0.1:{port}/token"
+client_secret_env = "TIGER_CLAW_REDACTION_TEST_SECRET"
+"#
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_tiger-claw"))
+        .args(["-n", "local", "-v", "-c"])
+        .arg(config.path())
+        .env("TIGER_CLAW_REDACTION_TEST_SECRET", CLIENT_SECRET)
+        .env("AWIN_SAS_DATA_IMPORT_API_SECRET", API_KEY)
+        .env("RUST_LOG", "trace")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let combined = format!("{}\n{}", stdout, stderr);
+    assert!(
+        combined.contains("Found token variable"),
+        "expected the token to be requested, output was:\n{}",
+        combined
+    );
+    // -v prints every exchange, headers and bodies included
+    assert!(
+        combined.contains("--> POST http://127.0.0.1") && combined.contains("<-- 500"),
+        "expected verbose HTTP exchanges, output was:\n{}",
+        combined
+    );
+    for secret in [CLIENT_SECRET, API_KEY, ACCESS_TOKEN] {
+        assert!(
+            !combined.contains(secret),
+            "secret {} leaked into output:\n{}",
+            secret,
+            combined
+        );
+    }
+}